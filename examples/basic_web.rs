@@ -9,7 +9,7 @@ fn to_string(bytes: &[u8]) -> String {
 
 fn main() {
     // Make a new asset store with google as the root
-    let store = from_url("http://www.google.com/");
+    let store = from_url("http://www.google.com/").unwrap();
     // Asynchronously load this file.
     store.load("basic_file.rs");
 
@@ -18,7 +18,7 @@ fn main() {
     // Create a file store for the local file system
     let file_store = from_directory("./examples/");
     // Create a file store for the google
-    let web_store = from_url("http://www.google.com/");
+    let web_store = from_url("http://www.google.com/").unwrap();
 
     // Make a MultiStore to combine all our other storage methods
     let mut combo = MultiStore::new();
@@ -0,0 +1,184 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, ErrorKind};
+use std::str::Split;
+
+/// A path to a resource, relative to the root of its store.
+///
+/// Paths are normalized when they are made: `\` is treated as `/`,
+/// empty and `.` components are dropped and `..` removes the component
+/// before it.  So `a/./b.png`, `a//b.png` and `a\b.png` are all the
+/// same path as `a/b.png`.  A leading `/` is ignored, since every path
+/// is relative to the root of its store.
+///
+/// A `..` that would climb above the root is kept, and stores refuse
+/// to load such paths.  `AssetPath::new` rejects them up front.
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct AssetPath {
+    path: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct InvalidAssetPath(pub String);
+
+impl fmt::Display for InvalidAssetPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Path leaves the store: {}", self.0)
+    }
+}
+
+impl Error for InvalidAssetPath {
+    fn description(&self) -> &str {
+        "path leaves the store"
+    }
+}
+
+impl From<InvalidAssetPath> for io::Error {
+    fn from(e: InvalidAssetPath) -> io::Error {
+        io::Error::new(ErrorKind::PermissionDenied, e)
+    }
+}
+
+impl AssetPath {
+    /// Normalize `path`, refusing paths that climb above the root.
+    pub fn new(path: &str) -> Result<AssetPath, InvalidAssetPath> {
+        let normal = AssetPath::from(path);
+        if normal.is_sandboxed() {
+            Ok(normal)
+        } else {
+            Err(InvalidAssetPath(path.to_string()))
+        }
+    }
+
+    fn normalize(raw: &str) -> AssetPath {
+        let mut parts: Vec<&str> = vec![];
+        let mut escapes = 0;
+        for part in raw.split(|c| c == '/' || c == '\\') {
+            match part {
+                "" | "." => { }
+                ".." => {
+                    if parts.pop().is_none() {
+                        escapes += 1;
+                    }
+                }
+                part => parts.push(part)
+            }
+        }
+
+        let mut all: Vec<&str> = (0..escapes).map(|_| "..").collect();
+        all.extend(parts.into_iter());
+        AssetPath { path: all.join("/") }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.path[..]
+    }
+
+    /// Whether the path stays inside the root of its store.
+    pub fn is_sandboxed(&self) -> bool {
+        self.components().next() != Some("..")
+    }
+
+    /// Whether this is the root of the store itself.
+    pub fn is_empty(&self) -> bool {
+        self.path.is_empty()
+    }
+
+    /// Append `other` to this path, treating it as relative to this one.
+    pub fn join<P: Into<AssetPath>>(&self, other: P) -> AssetPath {
+        let other = other.into();
+        AssetPath::normalize(&format!("{}/{}", self.path, other.path))
+    }
+
+    /// The path without its last component, or `None` for the root.
+    pub fn parent(&self) -> Option<AssetPath> {
+        if self.is_empty() {
+            return None;
+        }
+        match self.path.rfind('/') {
+            Some(i) => Some(AssetPath { path: self.path[..i].to_string() }),
+            None => Some(AssetPath { path: String::new() })
+        }
+    }
+
+    /// The last component of the path.
+    pub fn file_name(&self) -> Option<&str> {
+        self.components().last()
+    }
+
+    /// The last component without its extension.
+    pub fn file_stem(&self) -> Option<&str> {
+        self.file_name().map(|name| match name.rfind('.') {
+            Some(0) | None => name,
+            Some(i) => &name[..i]
+        })
+    }
+
+    /// The part of the last component after its final `.`.
+    pub fn extension(&self) -> Option<&str> {
+        self.file_name().and_then(|name| match name.rfind('.') {
+            Some(0) | None => None,
+            Some(i) => Some(&name[i + 1..])
+        })
+    }
+
+    pub fn components(&self) -> Components {
+        Components { parts: self.path.split('/'), empty: self.path.is_empty() }
+    }
+}
+
+/// The components of an `AssetPath`, see `AssetPath::components`.
+pub struct Components<'a> {
+    parts: Split<'a, char>,
+    empty: bool,
+}
+
+impl <'a> Iterator for Components<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        if self.empty { None } else { self.parts.next() }
+    }
+}
+
+impl <'a> From<&'a str> for AssetPath {
+    fn from(path: &'a str) -> AssetPath {
+        AssetPath::normalize(path)
+    }
+}
+
+impl <'a> From<&'a String> for AssetPath {
+    fn from(path: &'a String) -> AssetPath {
+        AssetPath::normalize(path)
+    }
+}
+
+impl From<String> for AssetPath {
+    fn from(path: String) -> AssetPath {
+        AssetPath::normalize(&path)
+    }
+}
+
+impl <'a> From<&'a AssetPath> for AssetPath {
+    fn from(path: &'a AssetPath) -> AssetPath {
+        path.clone()
+    }
+}
+
+impl AsRef<str> for AssetPath {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Display for AssetPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.path)
+    }
+}
+
+impl fmt::Debug for AssetPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.path)
+    }
+}
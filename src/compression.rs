@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::io::{Cursor, Read, Write};
+use std::path::Path;
+
+#[allow(unused_imports)] use std::io::ErrorKind;
+#[allow(unused_imports)] use std::io::Error as IoError;
+use std::io::Result as IoResult;
+
+use flate2::Compression;
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+use flate2::write::GzEncoder;
+
+/// A compression format that a resource can be stored in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Gzip,
+    /// Only available with the `zstd` feature.
+    Zstd,
+}
+
+impl Encoding {
+    /// The suffix added to the name of a compressed sibling file.
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            Encoding::Gzip => "gz",
+            Encoding::Zstd => "zst",
+        }
+    }
+
+    pub fn decode(&self, bytes: &[u8]) -> IoResult<Vec<u8>> {
+        let mut out = vec![];
+        try!(try!(self.decoder(bytes)).read_to_end(&mut out));
+        Ok(out)
+    }
+
+    /// Wrap `r` so that reading from it yields decoded bytes.
+    pub fn decoder<'a, R: Read + 'a>(&self, r: R) -> IoResult<Box<Read + 'a>> {
+        match *self {
+            Encoding::Gzip => Ok(Box::new(try!(GzDecoder::new(r)))),
+            Encoding::Zstd => zstd_decoder(r),
+        }
+    }
+
+    pub fn encode(&self, bytes: &[u8]) -> IoResult<Vec<u8>> {
+        match *self {
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(vec![], Compression::Default);
+                try!(encoder.write_all(bytes));
+                encoder.finish()
+            }
+            Encoding::Zstd => zstd_encode(bytes),
+        }
+    }
+
+    /// The decoded size recorded in the encoded bytes, if the format
+    /// keeps one.  Gzip only records it modulo 2^32.
+    pub fn decoded_size(&self, trailer: &[u8]) -> Option<u64> {
+        match *self {
+            Encoding::Gzip if trailer.len() >= 4 => {
+                let n = trailer.len();
+                Some(trailer[n - 4..].iter().rev().fold(0, |acc, &b| (acc << 8) | b as u64))
+            }
+            _ => None
+        }
+    }
+}
+
+/// Wrap an http body so that reading from it undoes the named
+/// `Content-Encoding`.  Several encodings, such as `gzip, deflate`,
+/// are undone in the reverse of the order they were applied.
+pub fn content_decoder<'a, R: Read + 'a>(content_encoding: &str, r: R)
+    -> IoResult<Box<Read + 'a>> {
+
+    let mut decoder: Box<Read + 'a> = Box::new(r);
+    for encoding in content_encoding.split(',').rev() {
+        decoder = match &encoding.trim().to_lowercase()[..] {
+            "gzip" | "x-gzip" => try!(Encoding::Gzip.decoder(decoder)),
+            "deflate" => try!(deflate_decoder(decoder)),
+            "" | "identity" => decoder,
+            other => {
+                return Err(IoError::new(
+                    ErrorKind::InvalidData,
+                    format!("Unsupported Content-Encoding: {}", other)
+                ));
+            }
+        };
+    }
+    Ok(decoder)
+}
+
+/// `deflate` is meant to be zlib, but plenty of servers send raw
+/// deflate streams instead, so the zlib header is checked for.
+fn deflate_decoder<'a, R: Read + 'a>(mut r: R) -> IoResult<Box<Read + 'a>> {
+    let mut head = [0; 2];
+    let mut have = 0;
+    while have < head.len() {
+        match try!(r.read(&mut head[have..])) {
+            0 => break,
+            n => { have += n; }
+        }
+    }
+    let zlib = have == 2 && head[0] & 0x0f == 8 &&
+               ((head[0] as u16) << 8 | head[1] as u16) % 31 == 0;
+    let r = Cursor::new(head[..have].to_vec()).chain(r);
+    if zlib {
+        Ok(Box::new(ZlibDecoder::new(r)))
+    } else {
+        Ok(Box::new(DeflateDecoder::new(r)))
+    }
+}
+
+#[cfg(feature = "zstd")]
+fn zstd_decoder<'a, R: Read + 'a>(r: R) -> IoResult<Box<Read + 'a>> {
+    Ok(Box::new(try!(::zstd::Decoder::new(r))))
+}
+
+#[cfg(not(feature = "zstd"))]
+fn zstd_decoder<'a, R: Read + 'a>(_: R) -> IoResult<Box<Read + 'a>> {
+    Err(IoError::new(ErrorKind::Other, "Built without zstd support"))
+}
+
+#[cfg(feature = "zstd")]
+fn zstd_encode(bytes: &[u8]) -> IoResult<Vec<u8>> {
+    let mut encoder = try!(::zstd::Encoder::new(vec![], 3));
+    try!(encoder.write_all(bytes));
+    encoder.finish()
+}
+
+#[cfg(not(feature = "zstd"))]
+fn zstd_encode(_: &[u8]) -> IoResult<Vec<u8>> {
+    Err(IoError::new(ErrorKind::Other, "Built without zstd support"))
+}
+
+#[cfg(feature = "zstd")]
+fn default_encodings() -> Vec<Encoding> {
+    vec![Encoding::Gzip, Encoding::Zstd]
+}
+
+#[cfg(not(feature = "zstd"))]
+fn default_encodings() -> Vec<Encoding> {
+    vec![Encoding::Gzip]
+}
+
+/// Decides which compressed siblings a store looks for when a
+/// resource is missing, based on the resource's extension.
+///
+/// ```ignore
+/// let mut policy = CompressionPolicy::new();
+/// policy.set("png", vec![]);
+/// policy.set("json", vec![Encoding::Zstd, Encoding::Gzip]);
+/// let store = from_directory("./assets/").compression(policy);
+/// ```
+#[derive(Clone, Debug)]
+pub struct CompressionPolicy {
+    default: Vec<Encoding>,
+    by_extension: HashMap<String, Vec<Encoding>>,
+}
+
+impl CompressionPolicy {
+    /// A policy that tries gzip, then zstd when built with the `zstd`
+    /// feature, for every resource.
+    pub fn new() -> CompressionPolicy {
+        CompressionPolicy {
+            default: default_encodings(),
+            by_extension: HashMap::new(),
+        }
+    }
+
+    /// Set the encodings to try, in order, for resources without a
+    /// more specific rule.
+    pub fn set_default(&mut self, encodings: Vec<Encoding>) {
+        self.default = encodings;
+    }
+
+    /// Set the encodings to try, in order, for resources with the
+    /// extension `ext`.  An empty list turns compression off for them.
+    pub fn set(&mut self, ext: &str, encodings: Vec<Encoding>) {
+        self.by_extension.insert(ext.to_lowercase(), encodings);
+    }
+
+    pub fn encodings_for(&self, path: &str) -> &[Encoding] {
+        let ext = Path::new(path).extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase());
+        match ext.and_then(|ext| self.by_extension.get(&ext)) {
+            Some(encodings) => &encodings[..],
+            None => &self.default[..]
+        }
+    }
+}
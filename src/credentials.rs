@@ -0,0 +1,53 @@
+use std::fmt;
+
+use hyper::header::{Authorization, Basic, Headers};
+
+/// Credentials sent with every request of a url store.
+///
+/// The secret parts are never shown by `Debug`, and never make it into
+/// the errors a store returns.
+#[derive(Clone)]
+pub enum Credentials {
+    /// http basic authentication.
+    Basic {
+        user: String,
+        password: String,
+    },
+    /// A bearer token, as used by most CDNs and OAuth servers.
+    Bearer(String),
+}
+
+impl Credentials {
+    pub fn basic(user: &str, password: &str) -> Credentials {
+        Credentials::Basic { user: user.to_string(), password: password.to_string() }
+    }
+
+    pub fn bearer(token: &str) -> Credentials {
+        Credentials::Bearer(token.to_string())
+    }
+
+    /// Set the `Authorization` header for these credentials.
+    pub fn apply(&self, headers: &mut Headers) {
+        match *self {
+            Credentials::Basic { ref user, ref password } => {
+                headers.set(Authorization(Basic {
+                    username: user.clone(),
+                    password: Some(password.clone()),
+                }));
+            }
+            Credentials::Bearer(ref token) => {
+                headers.set_raw("Authorization", vec![format!("Bearer {}", token).into_bytes()]);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Credentials::Basic { ref user, .. } =>
+                write!(f, "Basic {{ user: {:?}, password: <redacted> }}", user),
+            Credentials::Bearer(_) => write!(f, "Bearer(<redacted>)"),
+        }
+    }
+}
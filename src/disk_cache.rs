@@ -0,0 +1,496 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+
+use std::io::Result as IoResult;
+
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use hyper::client::Response;
+use time;
+
+/// What a server said identifies the version of a resource it sent.
+#[derive(Clone, Debug)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+/// How long a response may be served without asking the server again.
+#[derive(Clone, Copy, Debug)]
+pub struct Freshness {
+    /// Seconds since the epoch after which the response is stale.
+    pub expires: i64,
+    /// False if the server forbade storing the response.
+    pub store: bool,
+}
+
+impl Freshness {
+    /// Reads `Cache-Control` and `Expires`.  A response that says
+    /// nothing is stale straight away, so it is revalidated on every
+    /// use but still available offline.
+    pub fn of(res: &Response) -> Freshness {
+        let now = time::get_time().sec;
+        let header = |name: &str| res.headers.get_raw(name)
+            .and_then(|values| values.first())
+            .map(|value| String::from_utf8_lossy(value).into_owned());
+
+        let mut fresh = Freshness { expires: now, store: true };
+        if let Some(control) = header("Cache-Control") {
+            let mut max_age = None;
+            for directive in control.split(',').map(|d| d.trim().to_lowercase()) {
+                if directive == "no-store" {
+                    fresh.store = false;
+                } else if directive == "no-cache" {
+                    max_age = Some(0);
+                } else if directive.starts_with("max-age=") {
+                    max_age = max_age.or(directive[8..].parse::<i64>().ok());
+                }
+            }
+            if let Some(max_age) = max_age {
+                fresh.expires = now + max_age;
+                return fresh;
+            }
+        }
+        if let Some(at) = header("Expires")
+            .and_then(|date| time::strptime(&date, "%a, %d %b %Y %T GMT").ok()) {
+            fresh.expires = at.to_timespec().sec;
+        }
+        fresh
+    }
+
+    pub fn is_fresh(&self) -> bool {
+        time::get_time().sec < self.expires
+    }
+}
+
+/// A response read back from the cache.
+pub struct CacheEntry {
+    pub body: Vec<u8>,
+    pub validators: Validators,
+    pub freshness: Freshness,
+}
+
+static TEMP_COUNTER: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// A directory of downloaded resources that outlives the process, so
+/// url stores don't download everything again on every launch.
+///
+/// Each resource is kept as a body file and a small text file with
+/// its url, validators and expiry, both named after the SHA-256 of the
+/// url.  When the cache grows past its size cap, the entries that were
+/// least recently downloaded or revalidated are removed first.
+///
+/// Large downloads are written to a `.part` file as they arrive, next
+/// to a `.partmeta` file saying what the bytes must still match.  If
+/// one fails, or the process exits, the next attempt asks the server
+/// for only the missing bytes.  A download holds a `.claim` file while
+/// it writes, so two downloads of the same url, even from different
+/// processes, never write to the same file.  Claims left behind by a
+/// download that never finished are swept away when the cache is
+/// opened, so open it before starting any downloads that share it.
+///
+/// ```ignore
+/// let mut cache = try!(DiskCache::new("./cache/"));
+/// cache.set_max_size(256 << 20);
+/// let store = from_url("http://example.com/assets/").unwrap().disk_cache(cache);
+/// ```
+#[derive(Clone, Debug)]
+pub struct DiskCache {
+    dir: PathBuf,
+    max_size: Option<u64>,
+    resume_threshold: u64,
+}
+
+/// Downloads of at least this many bytes can be resumed when nothing
+/// else is set.
+const DEFAULT_RESUME_THRESHOLD: u64 = 4 << 20;
+
+impl DiskCache {
+    /// Use `dir` as the cache, creating it if needed.
+    pub fn new(dir: &str) -> IoResult<DiskCache> {
+        let dir = PathBuf::from(String::from(dir));
+        try!(fs::create_dir_all(&dir));
+        for entry in try!(fs::read_dir(&dir)) {
+            let path = try!(entry).path();
+            if path.extension().map_or(false, |ext| ext == "claim") {
+                try!(fs::remove_file(&path));
+            }
+        }
+        Ok(DiskCache { dir: dir, max_size: None, resume_threshold: DEFAULT_RESUME_THRESHOLD })
+    }
+
+    /// The most bytes the cache may use, counting bodies and metadata.
+    pub fn set_max_size(&mut self, bytes: u64) {
+        self.max_size = Some(bytes);
+    }
+
+    /// Downloads whose `Content-Length` is at least `bytes` are written
+    /// to disk as they arrive, so they can be resumed.
+    pub fn set_resume_threshold(&mut self, bytes: u64) {
+        self.resume_threshold = bytes;
+    }
+
+    pub fn resume_threshold(&self) -> u64 {
+        self.resume_threshold
+    }
+
+    fn name(url: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.input_str(url);
+        hasher.result_str()
+    }
+
+    fn paths(&self, url: &str) -> (PathBuf, PathBuf) {
+        let name = DiskCache::name(url);
+        (self.dir.join(format!("{}.body", name)), self.dir.join(format!("{}.meta", name)))
+    }
+
+    fn partial_paths(&self, url: &str) -> (PathBuf, PathBuf, PathBuf) {
+        let name = DiskCache::name(url);
+        (self.dir.join(format!("{}.part", name)),
+         self.dir.join(format!("{}.partmeta", name)),
+         self.dir.join(format!("{}.claim", name)))
+    }
+
+    /// The cached response for `url`, if there is a complete one.
+    pub fn get(&self, url: &str) -> Option<CacheEntry> {
+        let (body_path, meta_path) = self.paths(url);
+        let mut meta = String::new();
+        if File::open(&meta_path).and_then(|mut f| f.read_to_string(&mut meta)).is_err() {
+            return None;
+        }
+
+        let mut entry = CacheEntry {
+            body: vec![],
+            validators: Validators { etag: None, last_modified: None },
+            freshness: Freshness { expires: 0, store: true },
+        };
+        let mut size = None;
+        let mut same_url = false;
+        for line in meta.lines() {
+            let mut parts = line.splitn(2, ' ');
+            match (parts.next(), parts.next()) {
+                (Some("url"), Some(value)) => { same_url = value == url; }
+                (Some("size"), Some(value)) => { size = value.parse::<u64>().ok(); }
+                (Some("expires"), Some(value)) => {
+                    entry.freshness.expires = value.parse().unwrap_or(0);
+                }
+                (Some("etag"), Some(value)) => { entry.validators.etag = Some(value.to_string()); }
+                (Some("last-modified"), Some(value)) => {
+                    entry.validators.last_modified = Some(value.to_string());
+                }
+                _ => { }
+            }
+        }
+        if !same_url {
+            return None;
+        }
+
+        // A body that doesn't match its metadata was cut short.
+        if File::open(&body_path).and_then(|mut f| f.read_to_end(&mut entry.body)).is_err() ||
+           size != Some(entry.body.len() as u64) {
+            return None;
+        }
+        Some(entry)
+    }
+
+    /// Store a response, replacing any earlier one for `url`.
+    pub fn put(&self, url: &str, body: &[u8], validators: &Validators, freshness: Freshness)
+        -> IoResult<()> {
+
+        let (body_path, _) = self.paths(url);
+        if !freshness.store {
+            return self.remove(url);
+        }
+        try!(write_atomic(&body_path, body));
+        try!(self.write_meta(url, body.len() as u64, validators, freshness));
+        self.evict()
+    }
+
+    /// Record that the cached response for `url` was revalidated.
+    pub fn touch(&self, url: &str, size: u64, validators: &Validators, freshness: Freshness)
+        -> IoResult<()> {
+
+        if !freshness.store {
+            return self.remove(url);
+        }
+        self.write_meta(url, size, validators, freshness)
+    }
+
+    fn write_meta(&self, url: &str, size: u64, validators: &Validators, freshness: Freshness)
+        -> IoResult<()> {
+
+        let (_, meta_path) = self.paths(url);
+        let mut meta = format!("url {}\nsize {}\nexpires {}\n", url, size, freshness.expires);
+        if let Some(ref etag) = validators.etag {
+            meta.push_str(&format!("etag {}\n", etag));
+        }
+        if let Some(ref modified) = validators.last_modified {
+            meta.push_str(&format!("last-modified {}\n", modified));
+        }
+        write_atomic(&meta_path, meta.as_bytes())
+    }
+
+    pub fn remove(&self, url: &str) -> IoResult<()> {
+        let (body_path, meta_path) = self.paths(url);
+        for path in [meta_path, body_path].iter() {
+            if let Err(e) = fs::remove_file(path) {
+                if e.kind() != ErrorKind::NotFound {
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Take over the bytes an earlier download of `url` got, if they
+    /// can be resumed.  Until the claim is finished or dropped, no other
+    /// download sees them.
+    pub fn claim_partial(&self, url: &str) -> Option<PartialFile> {
+        let (part_path, meta_path, claim_path) = self.partial_paths(url);
+        let mut meta = String::new();
+        if File::open(&meta_path).and_then(|mut f| f.read_to_string(&mut meta)).is_err() {
+            return None;
+        }
+
+        let mut same_url = false;
+        let mut validator = None;
+        for line in meta.lines() {
+            let mut parts = line.splitn(2, ' ');
+            match (parts.next(), parts.next()) {
+                (Some("url"), Some(value)) => { same_url = value == url; }
+                (Some("validator"), Some(value)) => { validator = Some(value.to_string()); }
+                _ => { }
+            }
+        }
+        let validator = match validator {
+            Some(validator) if same_url => validator,
+            _ => { return None; }
+        };
+
+        // Only one download can create the claim, and any other starts
+        // over in memory.
+        if claim(&claim_path).is_err() {
+            return None;
+        }
+        let partial = match OpenOptions::new().read(true).append(true).open(&part_path) {
+            Ok(file) => self.partial_file(url, Some(validator), file),
+            Err(_) => {
+                let _ = fs::remove_file(&claim_path);
+                return None;
+            }
+        };
+        if partial.len() == 0 {
+            let _ = partial.discard();
+            return None;
+        }
+        Some(partial)
+    }
+
+    /// Start writing a download of `url` from its first byte.  Without
+    /// a validator the download can't be resumed safely, so it is
+    /// still written to disk but will be started over.  Fails if
+    /// another download of `url` holds the claim.
+    pub fn start_partial(&self, url: &str, validator: Option<&str>) -> IoResult<PartialFile> {
+        let (part_path, meta_path, claim_path) = self.partial_paths(url);
+        try!(claim(&claim_path));
+        let started = remove_if_present(&meta_path)
+            .and_then(|_| OpenOptions::new().read(true).write(true).create(true).truncate(true)
+                .open(&part_path))
+            .and_then(|file| match validator {
+                // Recorded up front, so the bytes can be resumed even if
+                // this process never gets to clean up.
+                Some(validator) => {
+                    let meta = format!("url {}\nvalidator {}\n", url, validator);
+                    write_atomic(&meta_path, meta.as_bytes()).map(|_| file)
+                }
+                None => Ok(file)
+            });
+        match started {
+            Ok(file) => Ok(self.partial_file(url, validator.map(|v| v.to_string()), file)),
+            Err(e) => {
+                let _ = fs::remove_file(&claim_path);
+                Err(e)
+            }
+        }
+    }
+
+    fn partial_file(&self, url: &str, validator: Option<String>, file: File) -> PartialFile {
+        let (part_path, meta_path, claim_path) = self.partial_paths(url);
+        PartialFile {
+            file: Some(file),
+            validator: validator,
+            part_path: part_path,
+            meta_path: meta_path,
+            claim_path: claim_path,
+        }
+    }
+
+    pub fn remove_partial(&self, url: &str) -> IoResult<()> {
+        let (part_path, meta_path, _) = self.partial_paths(url);
+        try!(remove_if_present(&meta_path));
+        remove_if_present(&part_path)
+    }
+
+    /// Remove every cached response and partial download, including
+    /// ones that a download in progress, or one that crashed, claimed.
+    pub fn clear(&self) -> IoResult<()> {
+        for entry in try!(fs::read_dir(&self.dir)) {
+            let path = try!(entry).path();
+            if is_cache_file(&path) {
+                try!(fs::remove_file(&path));
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove the stalest entries until the cache fits its cap.
+    fn evict(&self) -> IoResult<()> {
+        let max_size = match self.max_size {
+            Some(max_size) => max_size,
+            None => { return Ok(()); }
+        };
+
+        let mut total = 0;
+        let mut entries = vec![];
+        for entry in try!(fs::read_dir(&self.dir)) {
+            let path = try!(entry).path();
+            // Partial downloads may still be being written, so they are
+            // neither counted nor removed.
+            let ext = path.extension().and_then(|ext| ext.to_str()).map(|ext| ext.to_string());
+            if ext.as_ref().map_or(true, |ext| ext != "body" && ext != "meta") {
+                continue;
+            }
+            let stat = try!(fs::metadata(&path));
+            total += stat.len();
+            if path.extension().map_or(false, |ext| ext == "meta") {
+                let body_len = fs::metadata(path.with_extension("body")).map(|m| m.len()).unwrap_or(0);
+                entries.push((stat.modified().ok(), path, stat.len() + body_len));
+            }
+        }
+
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        for (_, meta_path, size) in entries.into_iter() {
+            if total <= max_size {
+                break;
+            }
+            let _ = fs::remove_file(&meta_path);
+            let _ = fs::remove_file(meta_path.with_extension("body"));
+            total = total.saturating_sub(size);
+        }
+        Ok(())
+    }
+}
+
+fn is_cache_file(path: &Path) -> bool {
+    path.extension().map_or(false, |ext| {
+        ext == "body" || ext == "meta" || ext == "part" || ext == "partmeta" || ext == "claim"
+    })
+}
+
+/// A partial download that one writer has claimed.  Dropping it
+/// without finishing leaves the bytes as the `.part` file of its url
+/// and gives up the claim, so a later attempt can resume them.
+pub struct PartialFile {
+    /// Only `None` once finished or discarded.
+    file: Option<File>,
+    validator: Option<String>,
+    part_path: PathBuf,
+    meta_path: PathBuf,
+    claim_path: PathBuf,
+}
+
+impl PartialFile {
+    /// How many bytes are already there.
+    pub fn len(&self) -> u64 {
+        self.file.as_ref().and_then(|file| file.metadata().ok()).map_or(0, |stat| stat.len())
+    }
+
+    /// What the resource must still match for the bytes to be used.
+    pub fn validator(&self) -> Option<&str> {
+        self.validator.as_ref().map(|v| &v[..])
+    }
+
+    /// Read back the finished download and forget it.
+    pub fn finish(mut self) -> IoResult<Vec<u8>> {
+        let mut file = self.file.take().unwrap();
+        let mut body = vec![];
+        let read = file.seek(SeekFrom::Start(0)).and_then(|_| file.read_to_end(&mut body));
+        drop(file);
+        try!(self.remove());
+        try!(read);
+        Ok(body)
+    }
+
+    /// Throw the bytes away, because they no longer fit the resource.
+    pub fn discard(mut self) -> IoResult<()> {
+        drop(self.file.take());
+        self.remove()
+    }
+
+    /// Remove the bytes, then give up the claim.
+    fn remove(&self) -> IoResult<()> {
+        let removed = remove_if_present(&self.meta_path)
+            .and_then(|_| remove_if_present(&self.part_path));
+        let released = remove_if_present(&self.claim_path);
+        removed.and(released)
+    }
+}
+
+impl Write for PartialFile {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        self.file.as_mut().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        self.file.as_mut().unwrap().flush()
+    }
+}
+
+impl Drop for PartialFile {
+    fn drop(&mut self) {
+        if self.file.take().is_none() {
+            return;
+        }
+        // Without a validator the bytes can't be resumed safely.
+        if self.validator.is_none() {
+            let _ = self.remove();
+        } else {
+            let _ = remove_if_present(&self.claim_path);
+        }
+    }
+}
+
+/// Create the claim file at `path`, failing if someone already holds it.
+fn claim(path: &Path) -> IoResult<()> {
+    OpenOptions::new().write(true).create_new(true).open(path).map(|_| ())
+}
+
+fn remove_if_present(path: &Path) -> IoResult<()> {
+    match fs::remove_file(path) {
+        Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        result => result
+    }
+}
+
+/// Writes through a temporary file so a crash never leaves a partial
+/// file behind under the real name.
+fn write_atomic(path: &Path, bytes: &[u8]) -> IoResult<()> {
+    let n = TEMP_COUNTER.fetch_add(1, Ordering::SeqCst);
+    let temp = path.with_extension(format!("{}.tmp", n));
+    let written = File::create(&temp)
+        .and_then(|mut f| f.write_all(bytes))
+        .and_then(|_| fs::rename(&temp, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written
+}
@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::marker::PhantomData;
+use std::sync::RwLock;
+
+use std::io::Result as IoResult;
+
+use crypto::aead::{AeadDecryptor, AeadEncryptor};
+use crypto::chacha20poly1305::ChaCha20Poly1305;
+use rand::{OsRng, Rng};
+
+use super::{AssetStore, AssetMetadata, ReadSeek, slice_range};
+use asset_path::AssetPath;
+use self::EncryptedStoreError::*;
+
+const NONCE_LEN: usize = 8;
+const TAG_LEN: usize = 16;
+
+/// Encrypt a resource for use with an `EncryptedStore`.  The path is
+/// authenticated along with the contents, so an encrypted resource
+/// only decrypts under the path it was encrypted for.  The normalized
+/// path is used, so `a/./b.png` and `a/b.png` are interchangeable.
+///
+/// The output is a random nonce, the ciphertext, then the tag.
+pub fn encrypt<P: Into<AssetPath>>(key: &[u8; 32], path: P, plain: &[u8]) -> IoResult<Vec<u8>> {
+    let path = path.into();
+    let mut nonce = [0; NONCE_LEN];
+    try!(OsRng::new()).fill_bytes(&mut nonce);
+
+    let mut out = vec![0; NONCE_LEN + plain.len() + TAG_LEN];
+    out[..NONCE_LEN].clone_from_slice(&nonce);
+    {
+        let (body, tag) = out[NONCE_LEN..].split_at_mut(plain.len());
+        ChaCha20Poly1305::new(key, &nonce, path.as_str().as_bytes()).encrypt(plain, body, tag);
+    }
+    Ok(out)
+}
+
+/// Decrypt a resource made by `encrypt`.  Returns `None` if the
+/// resource was tampered with, or encrypted with another key or path.
+pub fn decrypt<P: Into<AssetPath>>(key: &[u8; 32], path: P, sealed: &[u8]) -> Option<Vec<u8>> {
+    let path = path.into();
+    if sealed.len() < NONCE_LEN + TAG_LEN {
+        return None;
+    }
+    let (nonce, rest) = sealed.split_at(NONCE_LEN);
+    let (body, tag) = rest.split_at(rest.len() - TAG_LEN);
+
+    let mut plain = vec![0; body.len()];
+    if ChaCha20Poly1305::new(key, nonce, path.as_str().as_bytes()).decrypt(body, &mut plain, tag) {
+        Some(plain)
+    } else {
+        None
+    }
+}
+
+#[derive(Debug)]
+pub enum EncryptedStoreError<E> {
+    /// The resource failed authentication, so it was modified or
+    /// encrypted with a different key.
+    IntegrityError(String),
+    WrappedError(E)
+}
+
+/// Wraps another store whose resources were made with `encrypt`, and
+/// serves them decrypted.  ChaCha20-Poly1305 is used, so tampered
+/// resources are detected rather than decrypted into garbage.
+///
+/// Decrypted resources are kept by this store, and unloaded from the
+/// wrapped store so they are not held in memory twice.
+pub struct EncryptedStore<S, E> {
+    store: S,
+    key: [u8; 32],
+    plain: RwLock<HashMap<AssetPath, Vec<u8>>>,
+    _e: PhantomData<*const E>
+}
+
+impl <S: AssetStore<E>, E> EncryptedStore<S, E> {
+    pub fn new(store: S, key: [u8; 32]) -> EncryptedStore<S, E> {
+        EncryptedStore {
+            store: store,
+            key: key,
+            plain: RwLock::new(HashMap::new()),
+            _e: PhantomData
+        }
+    }
+
+    fn cached<O, F>(&self, path: &AssetPath, mapfn: &F) -> Option<O> where F: Fn(&[u8]) -> O {
+        match self.plain.read() {
+            Ok(plain) => plain.get(path).map(|x| mapfn(&x[..])),
+            Err(_) => None
+        }
+    }
+
+    /// Decrypts a resource that the wrapped store has finished loading.
+    fn unseal<O, F>(&self, path: &AssetPath, sealed: Option<Option<Vec<u8>>>, mapfn: &F) ->
+    Result<Option<O>, EncryptedStoreError<E>> where F: Fn(&[u8]) -> O {
+        match sealed {
+            Some(Some(plain)) => {
+                let out = mapfn(&plain[..]);
+                if let Ok(mut cache) = self.plain.write() {
+                    cache.insert(path.clone(), plain);
+                }
+                self.store.unload(path.as_str());
+                Ok(Some(out))
+            }
+            Some(None) => Err(IntegrityError(path.to_string())),
+            None => Ok(None)
+        }
+    }
+}
+
+impl <S: AssetStore<E>, E> AssetStore<EncryptedStoreError<E>> for EncryptedStore<S, E> {
+    fn load(&self, path: &str) {
+        if self.cached(&AssetPath::from(path), &|_: &[u8]| ()).is_none() {
+            self.store.load(path);
+        }
+    }
+
+    fn is_loaded(&self, path: &str) -> Result<bool, EncryptedStoreError<E>> {
+        self.map_resource(path, |_| ()).map(|x| x.is_some())
+    }
+
+    /// The reported size is that of the decrypted resource.
+    fn metadata(&self, path: &str) -> Result<AssetMetadata, EncryptedStoreError<E>> {
+        let mut meta = try!(self.store.metadata(path).map_err(WrappedError));
+        meta.stored_size = meta.size;
+        meta.size = meta.size.map(|size| size.saturating_sub((NONCE_LEN + TAG_LEN) as u64));
+        Ok(meta)
+    }
+
+    /// Authentication needs the whole resource, so it is decrypted
+    /// before the reader is returned.
+    fn open(&self, path: &str) -> Result<Box<ReadSeek>, EncryptedStoreError<E>> {
+        self.map_resource_block(path, |x| {
+            Box::new(Cursor::new(x.to_vec())) as Box<ReadSeek>
+        })
+    }
+
+    fn read_range(&self, path: &str, offset: u64, len: u64)
+        -> Result<Vec<u8>, EncryptedStoreError<E>> {
+
+        self.map_resource_block(path, |x| slice_range(x, offset, len).to_vec())
+    }
+
+    fn unload(&self, path: &str) {
+        if let Ok(mut cache) = self.plain.write() {
+            cache.remove(&AssetPath::from(path));
+        }
+        self.store.unload(path);
+    }
+
+    fn unload_everything(&self) {
+        if let Ok(mut cache) = self.plain.write() {
+            cache.clear();
+        }
+        self.store.unload_everything();
+    }
+
+    fn map_resource<O, F>(&self , path: &str, mapfn: F) -> Result<Option<O>, EncryptedStoreError<E>>
+        where F: Fn(&[u8]) -> O {
+
+        let key_path = AssetPath::from(path);
+        if let Some(out) = self.cached(&key_path, &mapfn) {
+            return Ok(Some(out));
+        }
+        let key = &self.key;
+        let sealed = try!(self.store.map_resource(path, |x| decrypt(key, &key_path, x))
+                          .map_err(WrappedError));
+        self.unseal(&key_path, sealed, &mapfn)
+    }
+
+    fn map_resource_block<O, F>(&self, path: &str, mapfn: F) -> Result<O, EncryptedStoreError<E>>
+        where F: Fn(&[u8]) -> O {
+
+        let key_path = AssetPath::from(path);
+        if let Some(out) = self.cached(&key_path, &mapfn) {
+            return Ok(out);
+        }
+        let key = &self.key;
+        let sealed = try!(self.store.map_resource_block(path, |x| decrypt(key, &key_path, x))
+                          .map_err(WrappedError));
+        match try!(self.unseal(&key_path, Some(sealed), &mapfn)) {
+            Some(out) => Ok(out),
+            None => unreachable!()
+        }
+    }
+}
@@ -1,15 +1,21 @@
 use std::collections::HashMap;
 use std::convert::From;
 use std::error::Error;
-use std::io::ErrorKind;
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom};
 use std::fs::File;
 #[allow(unused_imports)] use std::path::{self, Path, PathBuf};
 use std::string::String;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
 #[allow(unused_imports)] use std::thread::{self, spawn, sleep_ms};
-use hyper::client::{Client, Response};
+use std::time::{Duration, UNIX_EPOCH};
+use hyper::client::{pool, Client, Pool, Response};
+use hyper::header::{ContentLength, Headers};
+use hyper::method::Method;
+use hyper::net::DefaultConnector;
 use hyper::status::StatusCode;
-use hyper::Url;
+use hyper::{self, Url};
+use time;
 
 use std::io::Error as IoError;
 use std::io::Result as IoResult;
@@ -20,53 +26,283 @@ use std::io::Result as IoResult;
 // use hyper::status::StatusCode;
 
 
-use super::AssetStore;
+use super::{AssetStore, AssetWriter, AssetMetadata, ReadSeek, slice_range};
+use asset_path::AssetPath;
+use metadata::guess_content_type;
+use compression::{CompressionPolicy, Encoding, content_decoder};
+use credentials::Credentials;
+use disk_cache::{DiskCache, Freshness, PartialFile, Validators};
+use limits::{SizeLimits, TooLarge};
+use retry::{Failure, RetryError, RetryPolicy};
+use throttle::{Limiter, Throttle, Throttled};
+use timeouts::{DeadlineReader, TimedConnector, Timeouts, nanos, timed_out, with_deadline};
+use pack::PackError;
+use zip_backend::ZipError;
+
+pub type DistMap = Arc<RwLock<HashMap<AssetPath, IoResult<Vec<u8>>>>>;
+
+/// How many times each path has been changed through the store.
+type Generations = Arc<Mutex<HashMap<AssetPath, u64>>>;
+
+/// Where a backend hands over a resource it was asked to load.  If
+/// the store changes the path while the load is under way, the load
+/// is stale and its result is dropped rather than overwriting the
+/// newer bytes.
+pub struct Delivery {
+    mem: DistMap,
+    generations: Generations,
+    path: AssetPath,
+    generation: u64,
+}
+
+impl Delivery {
+    pub fn deliver(self, bytes: IoResult<Vec<u8>>) {
+        let generations = match self.generations.lock() {
+            Ok(generations) => generations,
+            Err(_) => { return; }
+        };
+        if generations.get(&self.path).cloned().unwrap_or(0) != self.generation {
+            return;
+        }
+        if let Ok(mut mem) = self.mem.write() {
+            mem.insert(self.path, bytes);
+        }
+    }
+}
 
-type DistMap = Arc<RwLock<HashMap<String, IoResult<Vec<u8>>>>>;
 pub trait IoBackend {
-    fn go_get(&self, path: &str, mem: DistMap);
+    fn go_get(&self, path: &AssetPath, delivery: Delivery);
+    /// Look up information about a resource without reading it.
+    fn metadata(&self, path: &AssetPath) -> IoResult<AssetMetadata>;
+    /// Open a resource for streaming, bypassing the store.
+    fn open(&self, path: &AssetPath) -> IoResult<Box<ReadSeek>>;
+    /// Fetch part of a resource, bypassing the store.
+    fn read_range(&self, path: &AssetPath, offset: u64, len: u64) -> IoResult<Vec<u8>>;
+}
+
+/// Copy an error out of the store.  The errors this crate produces
+/// are copied whole so callers can still downcast them, anything
+/// else keeps its kind and message.
+fn copy_error(e: &IoError) -> IoError {
+    let inner = match e.get_ref() {
+        Some(inner) => inner,
+        None => { return IoError::new(e.kind(), e.description().clone()); }
+    };
+    if let Some(inner) = inner.downcast_ref::<TooLarge>() {
+        IoError::new(e.kind(), inner.clone())
+    } else if let Some(inner) = inner.downcast_ref::<ZipError>() {
+        IoError::new(e.kind(), inner.clone())
+    } else if let Some(inner) = inner.downcast_ref::<PackError>() {
+        IoError::new(e.kind(), inner.clone())
+    } else if let Some(inner) = inner.downcast_ref::<RetryError>() {
+        IoError::new(e.kind(), inner.clone())
+    } else {
+        IoError::new(e.kind(), inner.to_string())
+    }
+}
+
+/// Refuse paths that could reach outside of a store's root, such as
+/// absolute paths or paths containing `..`.
+pub fn check_sandboxed(file: &str) -> IoResult<()> {
+    let escapes = Path::new(file).components().any(|c| match c {
+        path::Component::Normal(_) | path::Component::CurDir => false,
+        _ => true
+    }) || file.split(|c| c == '/' || c == '\\').any(|part| part == "..");
+
+    if escapes {
+        Err(IoError::new(
+            ErrorKind::PermissionDenied,
+            format!("Path leaves the store: {}", file)
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// A backend that can enumerate the resources it serves.
+pub trait ListBackend {
+    fn list(&self) -> IoResult<Vec<String>>;
+}
+
+/// A backend that can also modify the resources it serves.
+pub trait WriteBackend {
+    fn write(&self, path: &AssetPath, bytes: &[u8]) -> IoResult<()>;
+    fn remove(&self, path: &AssetPath) -> IoResult<()>;
+    fn rename(&self, from: &AssetPath, to: &AssetPath) -> IoResult<()>;
 }
 
 pub struct IoStore<Backend> {
     backend: Backend,
     mem: DistMap,
+    generations: Generations,
     //awaiting: HashSet<String>
 }
 
 pub fn from_directory(path: &str) -> IoStore<FsBackend> {
-    let path = PathBuf::from(String::from(path));
-    IoStore {
-        backend: FsBackend { path: path },
-        mem: Arc::new(RwLock::new(HashMap::new())),
-        //awaiting: HashSet::new(),
+    from_directories(&[path])
+}
+
+/// Make a store that overlays several directories.  Each path is
+/// looked up in `paths` in order and the first directory containing
+/// it wins, so later entries act as fallbacks for earlier ones.
+pub fn from_directories(paths: &[&str]) -> IoStore<FsBackend> {
+    let roots = paths.iter().map(|p| PathBuf::from(String::from(*p))).collect();
+    IoStore::new(FsBackend {
+        roots: roots,
+        case_index: None,
+        compression: None,
+        limits: SizeLimits::new(),
+    })
+}
+
+impl <B> IoStore<B> {
+    pub fn new(backend: B) -> IoStore<B> {
+        IoStore {
+            backend: backend,
+            mem: Arc::new(RwLock::new(HashMap::new())),
+            generations: Arc::new(Mutex::new(HashMap::new())),
+            //awaiting: HashSet::new(),
+        }
+    }
+
+    fn delivery(&self, path: &AssetPath) -> Delivery {
+        let generation = match self.generations.lock() {
+            Ok(generations) => generations.get(path).cloned().unwrap_or(0),
+            Err(_) => 0
+        };
+        Delivery {
+            mem: self.mem.clone(),
+            generations: self.generations.clone(),
+            path: path.clone(),
+            generation: generation,
+        }
+    }
+
+    /// Make loads of `paths` that are under way stale, then update the
+    /// loaded resources before any other load can land.
+    fn overtake<F>(&self, paths: &[&AssetPath], update: F)
+        where F: FnOnce(&mut HashMap<AssetPath, IoResult<Vec<u8>>>) {
+
+        let mut generations = match self.generations.lock() {
+            Ok(generations) => generations,
+            Err(_) => { return; }
+        };
+        for path in paths {
+            *generations.entry((*path).clone()).or_insert(0) += 1;
+        }
+        if let Ok(mut mem) = self.mem.write() {
+            update(&mut mem);
+        }
+    }
+
+    /// List every resource the backend can serve.
+    pub fn list(&self) -> IoResult<Vec<String>> where B: ListBackend {
+        self.backend.list()
     }
 }
 
+impl IoStore<FsBackend> {
+    /// Returns the directory that `path` would be loaded from, or
+    /// `None` if no directory contains it.
+    pub fn root_of<P: Into<AssetPath>>(&self, path: P) -> Option<&Path> {
+        match self.backend.locate(&path.into()) {
+            Ok(Some((root, _, _))) => Some(self.backend.roots[root].as_path()),
+            _ => None
+        }
+    }
+
+    /// Serve resources that are missing from compressed siblings, so
+    /// `foo.json` can be loaded from `foo.json.gz`.  The policy picks
+    /// which encodings are tried for each extension.  Decompression
+    /// happens on the loading thread.
+    pub fn compression(mut self, policy: CompressionPolicy) -> IoStore<FsBackend> {
+        self.backend.compression = Some(policy);
+        self
+    }
+
+    /// Refuse to load resources bigger than the given limits.  Sizes
+    /// are checked against file metadata before reading, and again
+    /// while reading in case a file grows or decompresses to more.
+    pub fn size_limits(mut self, limits: SizeLimits) -> IoStore<FsBackend> {
+        self.backend.limits = limits;
+        self
+    }
+
+    /// Resolve paths without regard to case, so `Textures/Grass.PNG`
+    /// finds `textures/grass.png`.  An exact match in any directory is
+    /// always preferred.
+    /// Otherwise a lowercase index of each directory is built on first
+    /// use, and a path that matches more than one file is an error.
+    pub fn case_insensitive(mut self) -> IoStore<FsBackend> {
+        self.backend.case_index = Some(Arc::new(RwLock::new(None)));
+        self
+    }
+
+    /// Throw away the case insensitive index so that it is rebuilt on
+    /// the next lookup.  Needed after files are added outside the store.
+    pub fn refresh_index(&self) {
+        self.backend.invalidate_index();
+    }
+}
+
+/// Normalize a path handed to the store, refusing paths that leave it.
+fn asset_path(path: &str) -> IoResult<AssetPath> {
+    AssetPath::new(path).map_err(|e| IoError::new(ErrorKind::PermissionDenied, e))
+}
+
 impl <B: IoBackend> AssetStore<IoError> for IoStore<B> {
+    /// Paths that leave the store are never loaded, asking for them
+    /// returns the error.
     fn load(&self, path: &str) {
         //if !self.awaiting.contains_equiv(&path) {
-            self.backend.go_get(path, self.mem.clone());
+        if let Ok(path) = asset_path(path) {
+            self.backend.go_get(&path, self.delivery(&path));
+        }
         //}
         //self.awaiting.insert(path.to_string());
     }
 
     fn is_loaded(&self, path: &str) -> Result<bool, IoError> {
+        let path = try!(asset_path(path));
         let mem = match self.mem.read() {
             Ok(mem) => { mem },
             Err(_) => { return Err(IoError::new(ErrorKind::Other, "Poisoned thread")); }
         };
 
-        match mem.get(path) {
+        match mem.get(&path) {
             Some(&Ok(_)) => Ok(true),
-            Some(&Err(ref e)) => Err(IoError::new(e.kind(), e.description().clone())),
+            Some(&Err(ref e)) => Err(copy_error(e)),
             None => Ok(false)
         }
         // Ok(true)
     }
 
+    fn metadata(&self, path: &str) -> IoResult<AssetMetadata> {
+        self.backend.metadata(&try!(asset_path(path)))
+    }
+
+    fn open(&self, path: &str) -> IoResult<Box<ReadSeek>> {
+        self.backend.open(&try!(asset_path(path)))
+    }
+
+    fn read_range(&self, path: &str, offset: u64, len: u64) -> IoResult<Vec<u8>> {
+        let cached = match self.map_resource(path, |x| slice_range(x, offset, len).to_vec()) {
+            Ok(cached) => cached,
+            Err(_) => None
+        };
+        match cached {
+            Some(bytes) => Ok(bytes),
+            None => self.backend.read_range(&try!(asset_path(path)), offset, len)
+        }
+    }
+
     fn unload(&self, path: &str) {
+        let path = match asset_path(path) {
+            Ok(path) => path,
+            Err(_) => { return; }
+        };
         match self.mem.write() {
-            Ok(mut mem) => { mem.remove(path); },
+            Ok(mut mem) => { mem.remove(&path); },
             Err(_) => { }
         }
     }
@@ -81,14 +317,15 @@ impl <B: IoBackend> AssetStore<IoError> for IoStore<B> {
     fn map_resource<O, F>(&self , path: &str, mapfn: F) ->
     Result<Option<O>, IoError> where F: Fn(&[u8]) -> O {
 
+        let path = try!(asset_path(path));
         let mem = match self.mem.read() {
             Ok(mem) => { mem },
             Err(_) => { return Err(IoError::new(ErrorKind::Other, "Poisoned thread")); }
         };
 
-        match mem.get(path) {
+        match mem.get(&path) {
             Some(&Ok(ref v)) => Ok(Some((mapfn)(&v[..]))),
-            Some(&Err(ref e)) => Err(IoError::new(e.kind(), e.description().clone())),
+            Some(&Err(ref e)) => Err(copy_error(e)),
             None => Ok(None)
         }
     }
@@ -110,122 +347,1244 @@ impl <B: IoBackend> AssetStore<IoError> for IoStore<B> {
     }
 }
 
+impl <B: IoBackend + WriteBackend> AssetWriter<IoError> for IoStore<B> {
+    fn save(&self, path: &str, bytes: &[u8]) -> IoResult<()> {
+        let path = try!(asset_path(path));
+        try!(self.backend.write(&path, bytes));
+        self.overtake(&[&path], |mem| { mem.insert(path.clone(), Ok(bytes.to_vec())); });
+        Ok(())
+    }
+
+    fn remove(&self, path: &str) -> IoResult<()> {
+        let path = try!(asset_path(path));
+        try!(self.backend.remove(&path));
+        self.overtake(&[&path], |mem| { mem.remove(&path); });
+        Ok(())
+    }
+
+    fn rename(&self, from: &str, to: &str) -> IoResult<()> {
+        let (from, to) = (try!(asset_path(from)), try!(asset_path(to)));
+        try!(self.backend.rename(&from, &to));
+        self.overtake(&[&from, &to], |mem| {
+            match mem.remove(&from) {
+                Some(entry) => { mem.insert(to.clone(), entry); }
+                None => { mem.remove(&to); }
+            }
+        });
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
 pub struct FsBackend {
-    path: PathBuf,
+    roots: Vec<PathBuf>,
+    case_index: Option<Arc<RwLock<Option<CaseIndex>>>>,
+    compression: Option<CompressionPolicy>,
+    limits: SizeLimits,
 }
 
+/// For each root, maps lowercased relative paths to every real
+/// relative path that lowercases to it.
+type CaseIndex = Vec<HashMap<String, Vec<String>>>;
+
+static TEMP_COUNTER: AtomicUsize = ATOMIC_USIZE_INIT;
+
 impl FsBackend {
-    fn process<P: AsRef<Path>>(path: P, filen: String) -> (String, IoResult<Vec<u8>>) {
-        use std::fs::PathExt;
-        use std::io::Read;
+    /// Find the root and full path that `filen` refers to.
+    fn resolve(&self, filen: &AssetPath) -> IoResult<Option<(usize, PathBuf)>> {
+        try!(check_sandboxed(filen.as_str()));
+        for (i, root) in self.roots.iter().enumerate() {
+            let exact = root.join(filen.as_str());
+            if exact.exists() {
+                return Ok(Some((i, exact)));
+            }
+        }
+        // Only fold case once no root has the exact path, so a file in a
+        // fallback root beats a near miss in an earlier one.
+        for (i, root) in self.roots.iter().enumerate() {
+            if let Some(found) = try!(self.lookup_folded(i, filen)) {
+                return Ok(Some((i, root.join(found))));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Like `resolve`, but falls back to compressed siblings allowed
+    /// by the compression policy.
+    fn locate(&self, filen: &AssetPath) -> IoResult<Option<(usize, PathBuf, Option<Encoding>)>> {
+        if let Some((root, base)) = try!(self.resolve(filen)) {
+            return Ok(Some((root, base, None)));
+        }
+        if let Some(ref policy) = self.compression {
+            for &encoding in policy.encodings_for(filen.as_str()).iter() {
+                let sibling = AssetPath::from(format!("{}.{}", filen, encoding.extension()));
+                if let Some((root, base)) = try!(self.resolve(&sibling)) {
+                    return Ok(Some((root, base, Some(encoding))));
+                }
+            }
+        }
+        Ok(None)
+    }
 
-        let mut base = path.as_ref().to_path_buf();
-        base.push(filen.clone());
+    fn not_found(&self, filen: &AssetPath) -> IoError {
+        let roots: Vec<String> = self.roots.iter().map(|root| match root.to_str() {
+            Some(s) => { s.to_string() },
+            None => { "{Bad Path}".to_string() }
+        }).collect();
+        IoError::new(
+            ErrorKind::NotFound,
+            format!("Given path does not exist: {} does not contain {}",
+                    roots.join(", "), filen)
+        )
+    }
 
+    fn read(&self, filen: &AssetPath) -> IoResult<Vec<u8>> {
         // is the path valid?
-        if !base.exists() {
-            return (
-                filen.clone(),
-                Err(
-                    IoError::new(
-                        ErrorKind::NotFound,
-                        format!("Given path does not exist: {} does not contain {}", match path.as_ref().to_str() {
-                            Some(s) => { s },
-                            None => { "{Bad Path}"}
-                        }, filen)
-                    )
-                )
-            );
-        }
-
-        match File::open(&base) {
-            Ok(mut f) => {
-                let mut buf: Vec<u8> = Vec::new();
-                match f.read_to_end(&mut buf) {
-                    Ok(_) => { (filen, Ok(buf)) }
-                    Err(e) => { (filen, Err(e)) }
-                }
-            },
-            Err(e) => { (filen, Err(e)) }
+        let (base, encoding) = match try!(self.locate(filen)) {
+            Some((_, base, encoding)) => (base, encoding),
+            None => { return Err(self.not_found(filen)); }
+        };
+
+        let f = try!(File::open(&base));
+        match encoding {
+            Some(encoding) => self.limits.read_to_end(filen.as_str(), try!(encoding.decoder(f))),
+            None => {
+                try!(self.limits.check(filen.as_str(), try!(f.metadata()).len()));
+                self.limits.read_to_end(filen.as_str(), f)
+            }
         }
     }
+
+    fn lookup_folded(&self, root: usize, filen: &AssetPath) -> IoResult<Option<String>> {
+        let index = match self.case_index {
+            Some(ref index) => index,
+            None => { return Ok(None); }
+        };
+        try!(self.build_index(index));
+
+        let index = match index.read() {
+            Ok(index) => index,
+            Err(_) => { return Err(IoError::new(ErrorKind::Other, "Poisoned thread")); }
+        };
+        let key = filen.as_str().to_lowercase();
+        match index.as_ref().and_then(|index| index[root].get(&key)) {
+            Some(found) if found.len() > 1 => Err(IoError::new(
+                ErrorKind::InvalidInput,
+                format!("Ambiguous path {}: matches {}", filen, found.join(", "))
+            )),
+            Some(found) => Ok(Some(found[0].clone())),
+            None => Ok(None)
+        }
+    }
+
+    fn build_index(&self, index: &RwLock<Option<CaseIndex>>) -> IoResult<()> {
+        let mut index = match index.write() {
+            Ok(index) => index,
+            Err(_) => { return Err(IoError::new(ErrorKind::Other, "Poisoned thread")); }
+        };
+        if index.is_some() {
+            return Ok(());
+        }
+
+        let mut built = vec![];
+        for root in self.roots.iter() {
+            let mut files = vec![];
+            if root.is_dir() {
+                try!(FsBackend::walk(root, root, &mut files));
+            }
+            let mut folded: HashMap<String, Vec<String>> = HashMap::new();
+            for file in files.into_iter() {
+                folded.entry(file.to_lowercase()).or_insert(vec![]).push(file);
+            }
+            built.push(folded);
+        }
+        *index = Some(built);
+        Ok(())
+    }
+
+    fn invalidate_index(&self) {
+        if let Some(ref index) = self.case_index {
+            if let Ok(mut index) = index.write() {
+                *index = None;
+            }
+        }
+    }
+
+    fn write_root(&self) -> IoResult<&Path> {
+        match self.roots.first() {
+            Some(root) => Ok(root.as_path()),
+            None => Err(IoError::new(ErrorKind::Other, "Store has no directories"))
+        }
+    }
+
+    fn walk(root: &Path, dir: &Path, out: &mut Vec<String>) -> IoResult<()> {
+        use std::fs;
+
+        for entry in try!(fs::read_dir(dir)) {
+            let entry = try!(entry);
+            let path = entry.path();
+            if try!(entry.file_type()).is_dir() {
+                try!(FsBackend::walk(root, &path, out));
+            } else if let Ok(rel) = path.strip_prefix(root) {
+                let parts: Vec<String> = rel.components()
+                    .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                    .collect();
+                out.push(parts.join("/"));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ListBackend for FsBackend {
+    /// Lists the union of every root, sorted and without duplicates.
+    fn list(&self) -> IoResult<Vec<String>> {
+        let mut all = vec![];
+        for root in self.roots.iter().filter(|root| root.is_dir()) {
+            try!(FsBackend::walk(root, root, &mut all));
+        }
+        all.sort();
+        all.dedup();
+        Ok(all)
+    }
+}
+
+impl WriteBackend for FsBackend {
+    /// Writes to a temporary file next to the target and renames it
+    /// into place, so readers never observe a half written file.
+    /// Writes always go to the first root.
+    fn write(&self, path: &AssetPath, bytes: &[u8]) -> IoResult<()> {
+        use std::fs;
+        use std::io::Write;
+
+        let target = match try!(self.resolve(path)) {
+            Some((0, existing)) => existing,
+            _ => try!(self.write_root()).join(path.as_str())
+        };
+        let name = match target.file_name().and_then(|n| n.to_str()) {
+            Some(name) => name.to_string(),
+            None => {
+                return Err(IoError::new(
+                    ErrorKind::InvalidInput,
+                    format!("Not a file path: {}", path)
+                ));
+            }
+        };
+        if let Some(parent) = target.parent() {
+            try!(fs::create_dir_all(parent));
+        }
+
+        let n = TEMP_COUNTER.fetch_add(1, Ordering::SeqCst);
+        let temp = target.with_file_name(format!(".{}.{}.tmp", name, n));
+        let written = File::create(&temp).and_then(|mut f| {
+            try!(f.write_all(bytes));
+            f.sync_all()
+        }).and_then(|_| fs::rename(&temp, &target));
+
+        if written.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+        self.invalidate_index();
+        written
+    }
+
+    /// Removes the copy that is currently being served, which may
+    /// uncover a copy of the same path in a later root.
+    fn remove(&self, path: &AssetPath) -> IoResult<()> {
+        let removed = match try!(self.resolve(path)) {
+            Some((_, existing)) => ::std::fs::remove_file(existing),
+            None => Err(IoError::new(
+                ErrorKind::NotFound,
+                format!("Given path does not exist: {}", path)
+            ))
+        };
+        self.invalidate_index();
+        removed
+    }
+
+    fn rename(&self, from: &AssetPath, to: &AssetPath) -> IoResult<()> {
+        use std::fs;
+
+        try!(check_sandboxed(to.as_str()));
+        let source = match try!(self.resolve(from)) {
+            Some((_, existing)) => existing,
+            None => {
+                return Err(IoError::new(
+                    ErrorKind::NotFound,
+                    format!("Given path does not exist: {}", from)
+                ));
+            }
+        };
+        let target = try!(self.write_root()).join(to.as_str());
+        if let Some(parent) = target.parent() {
+            try!(fs::create_dir_all(parent));
+        }
+        let renamed = fs::rename(source, target);
+        self.invalidate_index();
+        renamed
+    }
 }
 
 impl IoBackend for FsBackend {
-    fn go_get(&self, file: &str, mem: DistMap) {
-        let path = self.path.clone();
-        let file = file.to_string();
+    fn metadata(&self, file: &AssetPath) -> IoResult<AssetMetadata> {
+        let (base, encoding) = match try!(self.locate(file)) {
+            Some((_, base, encoding)) => (base, encoding),
+            None => { return Err(self.not_found(file)); }
+        };
+
+        let stat = try!(::std::fs::metadata(&base));
+        let mut meta = AssetMetadata::new();
+        meta.stored_size = Some(stat.len());
+        meta.size = match encoding {
+            Some(encoding) => {
+                let mut f = try!(File::open(&base));
+                try!(f.seek(SeekFrom::End(-(::std::cmp::min(stat.len(), 4) as i64))));
+                let mut trailer = vec![];
+                try!(f.read_to_end(&mut trailer));
+                encoding.decoded_size(&trailer)
+            }
+            None => Some(stat.len())
+        };
+        meta.modified = stat.modified().ok();
+        meta.content_type = guess_content_type(file.as_str());
+        Ok(meta)
+    }
+
+    /// Compressed siblings are decompressed up front, anything else
+    /// is read straight from disk.
+    fn open(&self, file: &AssetPath) -> IoResult<Box<ReadSeek>> {
+        match try!(self.locate(file)) {
+            Some((_, base, None)) => Ok(Box::new(try!(File::open(&base)))),
+            Some(_) => Ok(Box::new(io::Cursor::new(try!(self.read(file))))),
+            None => Err(self.not_found(file))
+        }
+    }
+
+    fn read_range(&self, file: &AssetPath, offset: u64, len: u64) -> IoResult<Vec<u8>> {
+        let mut reader = try!(self.open(file));
+        try!(reader.seek(SeekFrom::Start(offset)));
+        let mut buf = vec![];
+        try!(reader.take(len).read_to_end(&mut buf));
+        Ok(buf)
+    }
+
+    fn go_get(&self, file: &AssetPath, delivery: Delivery) {
+        let backend = self.clone();
+        let file = file.clone();
         thread::spawn(move || {
-            let (file, bytes) = FsBackend::process(path, file);
-            if let Ok(mut mem) = mem.write() {
-                mem.insert(file, bytes);
-            };
+            delivery.deliver(backend.read(&file));
         });
     }
 }
 
-pub fn from_url(base: &str) -> IoStore<NetBackend> {
-    IoStore {
-        backend: NetBackend { base: base.to_string() },
-        mem: Arc::new(RwLock::new(HashMap::new())),
-        //awaiting: HashSet::new(),
+/// Idle connections kept open to each host when nothing else is set.
+const DEFAULT_MAX_IDLE: usize = 5;
+
+/// Make a store that fetches resources relative to `base`, which has
+/// to be an absolute http or https url.  The base is treated as a
+/// directory whether or not it ends with `/`, and any query or
+/// fragment is dropped.  A user and password in the url are taken out
+/// of it and sent as `Credentials::basic`, so they never show up in
+/// errors or in the disk cache.
+pub fn from_url(base: &str) -> IoResult<IoStore<NetBackend>> {
+    let mut url = match Url::parse(base) {
+        Ok(url) => url,
+        Err(err) => {
+            // The url may hold a password, so it is left out.
+            return Err(IoError::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid base url: {}", err)
+            ));
+        }
+    };
+    let mut credentials = None;
+    if let Some(data) = url.relative_scheme_data_mut() {
+        let password = data.password.take();
+        if !data.username.is_empty() || password.is_some() {
+            credentials = Some(Credentials::basic(
+                &decode_userinfo(&data.username),
+                &decode_userinfo(password.as_ref().map(|p| &p[..]).unwrap_or(""))
+            ));
+        }
+        data.username = String::new();
+    }
+    url.query = None;
+    url.fragment = None;
+    if url.scheme != "http" && url.scheme != "https" {
+        return Err(IoError::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid base url {}: only http and https are supported", url.serialize())
+        ));
+    }
+    let mut base = url.serialize();
+    if !base.ends_with('/') {
+        base.push('/');
+    }
+
+    Ok(IoStore::new(NetBackend {
+        base: base,
+        limits: SizeLimits::new(),
+        client: NetBackend::client(DEFAULT_MAX_IDLE, &Timeouts::new()),
+        max_idle: DEFAULT_MAX_IDLE,
+        retry: RetryPolicy::new(),
+        timeouts: Timeouts::new(),
+        headers: vec![],
+        credentials: credentials,
+        hook: None,
+        validators: Arc::new(RwLock::new(HashMap::new())),
+        cache: None,
+        offline: Arc::new(AtomicBool::new(false)),
+        decode: true,
+        limiter: Limiter::new(&Throttle::new()),
+    }))
+}
+
+/// Percent-encode one path segment, leaving only the characters that
+/// never need escaping.
+fn encode_segment(segment: &str) -> String {
+    let mut out = String::with_capacity(segment.len());
+    for &b in segment.as_bytes() {
+        match b {
+            b'A'...b'Z' | b'a'...b'z' | b'0'...b'9' | b'-' | b'.' | b'_' | b'~' => out.push(b as char),
+            _ => out.push_str(&format!("%{:02X}", b))
+        }
+    }
+    out
+}
+
+/// Undo the percent-encoding of a user or password taken from a url.
+fn decode_userinfo(part: &str) -> String {
+    let bytes = part.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = if bytes[i] == b'%' && i + 2 < bytes.len() {
+            match ((bytes[i + 1] as char).to_digit(16), (bytes[i + 2] as char).to_digit(16)) {
+                (Some(high), Some(low)) => Some((high * 16 + low) as u8),
+                _ => None
+            }
+        } else {
+            None
+        };
+        match hex {
+            Some(b) => { out.push(b); i += 3; }
+            None => { out.push(bytes[i]); i += 1; }
+        }
     }
+    String::from_utf8_lossy(&out).into_owned()
 }
 
+impl IoStore<NetBackend> {
+    /// The url that `path` is fetched from.  Paths that leave the store
+    /// have no url.
+    pub fn url_for(&self, path: &str) -> IoResult<String> {
+        Ok(self.backend.url_for(&try!(asset_path(path))))
+    }
+
+    /// Refuse to load resources bigger than the given limits.  Sizes
+    /// are checked against `Content-Length` when the server sends it,
+    /// and downloads are stopped as soon as they pass the limit.
+    pub fn size_limits(mut self, limits: SizeLimits) -> IoStore<NetBackend> {
+        self.backend.limits = limits;
+        self
+    }
+
+    /// Keep at most `max` idle connections open to each host.  Every
+    /// request made by the store shares one connection pool, so
+    /// connections are reused instead of being opened per resource.
+    pub fn max_idle_connections(mut self, max: usize) -> IoStore<NetBackend> {
+        self.backend.client = NetBackend::client(max, &self.backend.timeouts);
+        self.backend.max_idle = max;
+        self
+    }
+
+    /// Give up on servers that are too slow to connect, answer or send
+    /// a resource.  A server that times out fails the load with
+    /// `ErrorKind::TimedOut`, so a stalled server can't hang a
+    /// blocking call forever.
+    pub fn timeouts(mut self, timeouts: Timeouts) -> IoStore<NetBackend> {
+        self.backend.client = NetBackend::client(self.backend.max_idle, &timeouts);
+        self.backend.timeouts = timeouts;
+        self
+    }
+
+    /// Fetch `path` again, blocking until done.  If it is loaded and
+    /// the server sent an `ETag` or `Last-Modified` for it, the request
+    /// is conditional and the loaded bytes are kept when the server
+    /// answers `304 Not Modified`.  On error the loaded bytes are left
+    /// as they were.  A store that is offline can't ask the server, so
+    /// reloading fails without looking at the disk cache.
+    pub fn reload(&self, path: &str) -> IoResult<ReloadStatus> {
+        let file = try!(asset_path(path));
+        if self.backend.is_offline() {
+            return Err(IoError::new(
+                ErrorKind::NotConnected,
+                format!("Can't reload {} while the store is offline", file)
+            ));
+        }
+        let loaded = match self.mem.read() {
+            Ok(mem) => match mem.get(&file) {
+                Some(&Ok(_)) => true,
+                _ => false
+            },
+            Err(_) => { return Err(IoError::new(ErrorKind::Other, "Poisoned thread")); }
+        };
+        let validators = if loaded {
+            match self.backend.validators.read() {
+                Ok(validators) => validators.get(&file).cloned(),
+                Err(_) => None
+            }
+        } else {
+            None
+        };
+
+        let path = self.backend.url_for(&file);
+        match try!(self.backend.download_if_changed(&path, &file, validators)) {
+            None => Ok(ReloadStatus::Unchanged),
+            Some(data) => {
+                self.overtake(&[&file], |mem| { mem.insert(file.clone(), Ok(data)); });
+                Ok(ReloadStatus::Updated)
+            }
+        }
+    }
+
+    /// Send a header with every request, such as `User-Agent`.
+    pub fn header(mut self, name: &str, value: &str) -> IoStore<NetBackend> {
+        self.backend.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Authenticate every request with `credentials`.
+    pub fn credentials(mut self, credentials: Credentials) -> IoStore<NetBackend> {
+        self.backend.credentials = Some(credentials);
+        self
+    }
+
+    /// Run `hook` on every request just before it is sent, after the
+    /// default headers and credentials are set.  It may change the url
+    /// too, for example to sign it or to add a fresh token.  Errors
+    /// still name the url from before the hook ran.
+    pub fn request_hook<F>(mut self, hook: F) -> IoStore<NetBackend>
+    where F: Fn(&mut Url, &mut Headers) + Send + Sync + 'static {
+        self.backend.hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Try failed loads again as the policy allows, instead of keeping
+    /// the first error.
+    pub fn retry(mut self, policy: RetryPolicy) -> IoStore<NetBackend> {
+        self.backend.retry = policy;
+        self
+    }
+
+    /// Whether gzip and deflate bodies are decoded, which they are
+    /// unless turned off.  When decoding, full downloads advertise
+    /// `Accept-Encoding: gzip, deflate`; in raw mode every request asks
+    /// for `identity`, so resources arrive exactly as the server
+    /// stores them.  Ranged reads always ask for `identity`, since
+    /// ranges of an encoded body can't be decoded on their own.
+    pub fn decode_content(mut self, decode: bool) -> IoStore<NetBackend> {
+        self.backend.decode = decode;
+        self
+    }
+
+    /// Limit concurrent requests per host and the bandwidth used by
+    /// the store.  Requests over the limit queue up until a slot is
+    /// free, so `load_all` on many resources doesn't flood the server.
+    pub fn throttle(mut self, throttle: Throttle) -> IoStore<NetBackend> {
+        self.backend.limiter = Limiter::new(&throttle);
+        self
+    }
+
+    /// Keep every download in `cache`.  Cached resources are served
+    /// without touching the network until the server's `Cache-Control`
+    /// or `Expires` says they are stale, and are then revalidated with
+    /// a conditional request.
+    pub fn disk_cache(mut self, cache: DiskCache) -> IoStore<NetBackend> {
+        self.backend.cache = Some(cache);
+        self
+    }
+
+    /// While offline the store never touches the network.  Resources
+    /// are served from the disk cache, stale or not, and anything that
+    /// isn't cached fails with `ErrorKind::NotFound`.
+    pub fn set_offline(&self, offline: bool) {
+        self.backend.offline.store(offline, Ordering::SeqCst);
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.backend.is_offline()
+    }
+
+    /// Empty the disk cache, if there is one.  Loaded resources stay
+    /// loaded.
+    pub fn clear_cache(&self) -> IoResult<()> {
+        match self.backend.cache {
+            Some(ref cache) => cache.clear(),
+            None => Ok(())
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct NetBackend {
-    base: String
+    base: String,
+    limits: SizeLimits,
+    /// Shared by every clone of the backend, and so by every loading
+    /// thread.
+    client: Arc<Client>,
+    max_idle: usize,
+    retry: RetryPolicy,
+    timeouts: Timeouts,
+    headers: Vec<(String, String)>,
+    credentials: Option<Credentials>,
+    hook: Option<RequestHook>,
+    /// The validators of the last full download of each resource.
+    validators: Arc<RwLock<HashMap<AssetPath, Validators>>>,
+    cache: Option<DiskCache>,
+    /// Shared by every clone.  Each attempt checks it, so a load that
+    /// is waiting to retry when the store goes offline stops at its
+    /// next attempt and is served from the disk cache instead.
+    offline: Arc<AtomicBool>,
+    decode: bool,
+    /// Shared by every clone, so limits hold across loading threads.
+    limiter: Arc<Limiter>,
+}
+
+/// The answer to one request for a resource.
+struct Fetched {
+    /// `None` if the server said the resource has not changed.
+    body: Option<Vec<u8>>,
+    validators: Validators,
+    freshness: Freshness,
+}
+
+/// The outcome of `IoStore::reload`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReloadStatus {
+    /// The server confirmed the loaded copy is current, so it was kept.
+    Unchanged,
+    /// A new copy was downloaded and replaced the loaded one.
+    Updated,
+}
+
+/// Called on every request just before it is sent.
+type RequestHook = Arc<Fn(&mut Url, &mut Headers) + Send + Sync>;
+
+/// Reads a `Retry-After` header, which is either a number of seconds
+/// or an http date.
+fn retry_after(value: &str) -> Option<Duration> {
+    if let Ok(secs) = value.trim().parse() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = match time::strptime(value.trim(), "%a, %d %b %Y %T GMT") {
+        Ok(at) => at.to_timespec().sec,
+        Err(_) => { return None; }
+    };
+    let now = time::get_time().sec;
+    Some(Duration::from_secs(if at > now { (at - now) as u64 } else { 0 }))
 }
 
 impl NetBackend {
-    fn http_get(path: &String) -> Result<Response, String> {
-        let url = match Url::parse(&path) {
-            Ok(url) => url,
-            Err(parse_err) => return Err(
-                format!("Error parsing url: {}", parse_err)
-            ),
+    fn url_for(&self, file: &AssetPath) -> String {
+        let segments: Vec<String> = file.components().map(encode_segment).collect();
+        vec![self.base.clone(), segments.join("/")].concat()
+    }
+
+    fn client(max_idle: usize, timeouts: &Timeouts) -> Arc<Client> {
+        let connector = TimedConnector(DefaultConnector::default());
+        let mut client = Client::with_connector(Pool::with_connector(pool::Config { max_idle: max_idle }, connector));
+        client.set_read_timeout(timeouts.first_byte());
+        client.set_write_timeout(timeouts.header_wait());
+        Arc::new(client)
+    }
+
+    fn http_get(&self, path: &str, headers: Headers) -> Result<Response, hyper::Error> {
+        self.http_request(Method::Get, path, headers)
+    }
+
+    /// Sends a request and waits for the response headers.  Reads from
+    /// the connection give up once the header wait is over, so a server
+    /// that never answers can't hold on to the calling thread.
+    fn http_request(&self, method: Method, path: &str, mut headers: Headers)
+        -> Result<Response, hyper::Error> {
+
+        let mut url = try!(Url::parse(&path).map_err(hyper::Error::Uri));
+        for &(ref name, ref value) in self.headers.iter() {
+            if headers.get_raw(name).is_none() {
+                headers.set_raw(name.clone(), vec![value.clone().into_bytes()]);
+            }
+        }
+        if let Some(ref credentials) = self.credentials {
+            credentials.apply(&mut headers);
+        }
+        if let Some(ref hook) = self.hook {
+            hook(&mut url, &mut headers);
+        }
+
+        let wait = match self.timeouts.header_wait() {
+            Some(wait) => wait,
+            None => { return self.client.request(method, url).headers(headers).send(); }
         };
 
-        let mut client = Client::new();
-        let request = client.get(url);
+        let deadline = time::precise_time_ns() + nanos(wait);
+        let client = &self.client;
+        match with_deadline(Some(deadline), || client.request(method, url).headers(headers).send()) {
+            Err(hyper::Error::Io(ref err))
+                if err.kind() == ErrorKind::WouldBlock || err.kind() == ErrorKind::TimedOut => {
+                Err(hyper::Error::Io(timed_out(path)))
+            }
+            res => res
+        }
+    }
 
-        request.send().map_err(|e| e.to_string())
+    /// Turns a failed request into an io error, keeping the kind of
+    /// io errors so timeouts and connection failures can be told apart.
+    fn http_error(what: &str, path: &str, err: hyper::Error) -> IoError {
+        let kind = match err {
+            hyper::Error::Io(ref err) => err.kind(),
+            _ => ErrorKind::Other
+        };
+        IoError::new(kind, format!("Error fetching {} over http {}: {}", what, path, err))
     }
-}
 
-impl IoBackend for NetBackend {
-    fn go_get(&self, file: &str, mem: DistMap) {
-        use std::io::Read;
+    /// One attempt at downloading `file`.  With validators the request
+    /// is conditional, and no body is returned if the server says the
+    /// resource has not changed.  Otherwise a partial download left in
+    /// the disk cache is resumed if the resource still matches it.
+    fn fetch(&self, path: &str, file: &AssetPath, validators: Option<&Validators>)
+        -> Result<Fetched, Failure> {
 
-        let path = vec![self.base.clone(), file.to_string()].concat();
-        let file = file.to_string();
-        spawn(move || {
-            let mut res = match NetBackend::http_get(&path) {
-                Ok(res) => res,
-                Err(err) => {
-                    let error = Err(IoError::new(
-                        ErrorKind::Other,
-                        format!("Error fetching file over http {}: {}", path, err)
-                    ));
-                    let mut map = mem.write().unwrap();
-                    map.insert(file, error);
-                    return;
+        if self.is_offline() {
+            return Err(Failure::io(IoError::new(
+                ErrorKind::NotFound,
+                format!("Error fetching file {}: the store went offline", path)
+            )));
+        }
+        let mut headers = Headers::new();
+        if let Some(validators) = validators {
+            if let Some(ref etag) = validators.etag {
+                headers.set_raw("If-None-Match", vec![etag.clone().into_bytes()]);
+            }
+            if let Some(ref modified) = validators.last_modified {
+                headers.set_raw("If-Modified-Since", vec![modified.clone().into_bytes()]);
+            }
+        }
+        let accept = if self.decode { "gzip, deflate" } else { "identity" };
+        headers.set_raw("Accept-Encoding", vec![accept.as_bytes().to_vec()]);
+        // Dropping the claim on any error leaves the bytes for the next
+        // attempt.
+        let mut partial = match (self.cache.as_ref(), validators) {
+            (Some(cache), None) => cache.claim_partial(path),
+            _ => None
+        };
+        if let Some(ref partial) = partial {
+            let validator = partial.validator().unwrap_or("");
+            headers.set_raw("Range", vec![format!("bytes={}-", partial.len()).into_bytes()]);
+            headers.set_raw("If-Range", vec![validator.as_bytes().to_vec()]);
+        }
+
+        // Waiting for a slot isn't part of the transfer.
+        let permit = Limiter::acquire(&self.limiter, path);
+        let deadline = self.timeouts.deadline();
+        let res = match self.http_get(path, headers) {
+            Ok(res) => res,
+            Err(err) => { return Err(Failure::io(NetBackend::http_error("file", path, err))); }
+        };
+
+        let fresh = Validators {
+            etag: NetBackend::header(&res, "ETag"),
+            last_modified: NetBackend::header(&res, "Last-Modified"),
+        };
+        let freshness = Freshness::of(&res);
+        if let (StatusCode::NotModified, Some(old)) = (res.status, validators) {
+            // Servers may leave out validators that haven't changed.
+            return Ok(Fetched {
+                body: None,
+                validators: Validators {
+                    etag: fresh.etag.or(old.etag.clone()),
+                    last_modified: fresh.last_modified.or(old.last_modified.clone()),
+                },
+                freshness: freshness,
+            });
+        }
+        let resumed = match (res.status, partial.as_ref().map(|partial| partial.len())) {
+            (StatusCode::PartialContent, Some(have)) => {
+                let start = NetBackend::header(&res, "Content-Range")
+                    .and_then(|range| content_range_start(&range));
+                if start != Some(have) {
+                    drop(permit);
+                    return self.restart(path, file, partial);
                 }
+                Some(have)
+            }
+            (StatusCode::RangeNotSatisfiable, Some(_)) => {
+                drop(permit);
+                return self.restart(path, file, partial);
+            }
+            _ => None
+        };
+        if res.status != StatusCode::Ok && resumed.is_none() {
+            let kind = match res.status {
+                StatusCode::NotFound => ErrorKind::NotFound,
+                _ => ErrorKind::Other
             };
+            return Err(Failure {
+                error: IoError::new(
+                    kind,
+                    format!("Error fetching file over http {}: {}", path, res.status)
+                ),
+                status: Some(res.status.to_u16()),
+                retry_after: NetBackend::header(&res, "Retry-After")
+                    .and_then(|value| retry_after(&value)),
+            });
+        }
+
+        if resumed.is_none() {
+            // The whole resource is coming, so the old bytes are no use.
+            if let Some(partial) = partial.take() {
+                let _ = partial.discard();
+            }
+        }
+        let start = resumed.unwrap_or(0);
+        let expected = res.headers.get::<ContentLength>().map(|len| start + len.0);
+        try!(match expected {
+            Some(len) => self.limits.check(file.as_str(), len),
+            None => Ok(())
+        }.map_err(Failure::io));
+
+        let encoding = NetBackend::header(&res, "Content-Encoding");
+        let encoding = encoding.as_ref().map(|e| &e[..]);
+        let body = Throttled::new(DeadlineReader::new(res, deadline, path), permit);
+        let data = try!(match self.cache {
+            Some(ref cache) if resumed.is_some() ||
+                               expected.map_or(false, |len| len >= cache.resume_threshold()) => {
+                // If-Range needs a strong validator, and weak etags
+                // start with `W/`.
+                let validator = fresh.etag.clone()
+                    .and_then(|etag| if etag.starts_with("W/") { None } else { Some(etag) })
+                    .or(fresh.last_modified.clone());
+                self.download_partial(cache, path, file, body, partial, expected, validator)
+                    .and_then(|raw| self.read_body(file, encoding, &raw[..]))
+            }
+            _ => self.read_body(file, encoding, body)
+        }.map_err(Failure::io));
+        Ok(Fetched { body: Some(data), validators: fresh, freshness: freshness })
+    }
+
+    /// Read a body, undoing its `Content-Encoding` unless the store is
+    /// raw.  The size limit applies to the decoded bytes, so a small
+    /// body can't expand into an enormous one.
+    fn read_body<R: Read>(&self, file: &AssetPath, encoding: Option<&str>, body: R)
+        -> IoResult<Vec<u8>> {
+
+        match encoding {
+            Some(encoding) if self.decode => {
+                self.limits.read_to_end(file.as_str(), try!(content_decoder(encoding, body)))
+            }
+            _ => self.limits.read_to_end(file.as_str(), body)
+        }
+    }
+
+    /// Forget a partial download that no longer fits the resource and
+    /// fetch it from the start.
+    fn restart(&self, path: &str, file: &AssetPath, partial: Option<PartialFile>)
+        -> Result<Fetched, Failure> {
+
+        if let Some(partial) = partial {
+            try!(partial.discard().map_err(Failure::io));
+        }
+        self.fetch(path, file, None)
+    }
+
+    /// Writes the body to a partial file of `path` as it arrives, after
+    /// the bytes already in `resumed` if there is one.  What was written
+    /// stays on disk if the transfer fails, so the next attempt can
+    /// resume.  While another download of `path` is writing its own
+    /// partial file, the body is read into memory instead.
+    fn download_partial<R: Read>(&self, cache: &DiskCache, path: &str, file: &AssetPath,
+                                 body: R, resumed: Option<PartialFile>, expected: Option<u64>,
+                                 validator: Option<String>) -> IoResult<Vec<u8>> {
+
+        let mut out = match resumed {
+            Some(resumed) => resumed,
+            None => match cache.start_partial(path, validator.as_ref().map(|v| &v[..])) {
+                Ok(out) => out,
+                Err(ref e) if e.kind() == ErrorKind::AlreadyExists => {
+                    return self.limits.read_to_end(file.as_str(), body);
+                }
+                Err(e) => { return Err(e); }
+            }
+        };
+        let start = out.len();
+        let copied = try!(self.limits.copy(file.as_str(), body, &mut out, start));
+        if let Some(expected) = expected {
+            if start + copied < expected {
+                return Err(IoError::new(
+                    ErrorKind::UnexpectedEof,
+                    format!("Error fetching file over http {}: got {} of {} bytes",
+                            path, start + copied, expected)
+                ));
+            }
+        }
+        out.finish()
+    }
+
+    fn is_offline(&self) -> bool {
+        self.offline.load(Ordering::SeqCst)
+    }
+
+    /// The cached body of `path`, for use while offline.
+    fn offline_body(&self, path: &str) -> IoResult<Vec<u8>> {
+        match self.cache.as_ref().and_then(|cache| cache.get(path)) {
+            Some(entry) => Ok(entry.body),
+            None => Err(IoError::new(
+                ErrorKind::NotFound,
+                format!("Error fetching file {}: not in the offline cache", path)
+            ))
+        }
+    }
 
-            if res.status == StatusCode::Ok {
-                let mut map = mem.write().unwrap();
-                let mut data = vec![];
-                let res = res.read_to_end(&mut data);
-                map.insert(file, res.map(|_| data));
+    /// Get `file` for a load, through the disk cache when there is one.
+    /// Offline, or once the store goes offline, it comes from the disk
+    /// cache alone.
+    fn download(&self, path: &str, file: &AssetPath) -> IoResult<Vec<u8>> {
+        if self.is_offline() {
+            return self.offline_body(path);
+        }
+        match self.download_if_changed(path, file, None) {
+            Ok(Some(body)) => Ok(body),
+            Ok(None) => Err(IoError::new(
+                ErrorKind::InvalidData,
+                format!("Error fetching file over http {}: not modified, but nothing is cached", path)
+            )),
+            Err(_) if self.is_offline() => self.offline_body(path),
+            Err(err) => Err(err)
+        }
+    }
+
+    /// Get `file`, through the disk cache when there is one.  `known`
+    /// are the validators of a copy the caller already holds; `None`
+    /// is returned only if the server says that copy is current.
+    fn download_if_changed(&self, path: &str, file: &AssetPath, known: Option<Validators>)
+        -> IoResult<Option<Vec<u8>>> {
+
+        let cached = self.cache.as_ref().and_then(|cache| cache.get(path));
+        if let Some(ref entry) = cached {
+            if known.is_none() && entry.freshness.is_fresh() {
+                return Ok(Some(entry.body.clone()));
+            }
+        }
+
+        let revalidating = known.is_none();
+        let validators = known.or_else(|| {
+            cached.as_ref()
+                .map(|entry| entry.validators.clone())
+                .and_then(|v| if v.is_empty() { None } else { Some(v) })
+        });
+        let fetched = try!(self.retry.run(path, || self.fetch(path, file, validators.as_ref())));
+
+        if let Ok(mut known) = self.validators.write() {
+            if fetched.validators.is_empty() {
+                known.remove(file);
             } else {
-                let error = Err(IoError::new(
+                known.insert(file.clone(), fetched.validators.clone());
+            }
+        }
+
+        // The cache is only an optimization, so failing to write it
+        // doesn't fail the load.
+        match fetched.body {
+            Some(body) => {
+                if let Some(ref cache) = self.cache {
+                    let _ = cache.put(path, &body, &fetched.validators, fetched.freshness);
+                }
+                Ok(Some(body))
+            }
+            None => {
+                if let (Some(cache), Some(entry)) = (self.cache.as_ref(), cached.as_ref()) {
+                    let size = entry.body.len() as u64;
+                    let _ = cache.touch(path, size, &fetched.validators, fetched.freshness);
+                }
+                if revalidating {
+                    Ok(cached.map(|entry| entry.body))
+                } else {
+                    Ok(None)
+                }
+            }
+        }
+    }
+
+    fn header(res: &Response, name: &str) -> Option<String> {
+        res.headers.get_raw(name)
+            .and_then(|values| values.first())
+            .map(|value| String::from_utf8_lossy(value).into_owned())
+    }
+
+    fn response_metadata(res: &Response, file: &str) -> AssetMetadata {
+        let mut meta = AssetMetadata::new();
+        meta.size = res.headers.get::<ContentLength>().map(|len| len.0);
+        meta.modified = NetBackend::header(res, "Last-Modified")
+            .and_then(|date| time::strptime(&date, "%a, %d %b %Y %T GMT").ok())
+            .map(|date| date.to_timespec().sec)
+            .and_then(|secs| if secs >= 0 { Some(secs as u64) } else { None })
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+        meta.content_type = NetBackend::header(res, "Content-Type")
+            .or_else(|| guess_content_type(file));
+        meta.etag = NetBackend::header(res, "ETag");
+        meta
+    }
+}
+
+impl IoBackend for NetBackend {
+    /// Sends a `HEAD` request, so only the headers are transferred.
+    fn metadata(&self, file: &AssetPath) -> IoResult<AssetMetadata> {
+        let path = self.url_for(file);
+        if self.is_offline() {
+            let mut meta = AssetMetadata::new();
+            meta.size = Some(try!(self.offline_body(&path)).len() as u64);
+            meta.content_type = guess_content_type(file.as_str());
+            return Ok(meta);
+        }
+        let mut headers = Headers::new();
+        headers.set_raw("Accept-Encoding", vec![b"identity".to_vec()]);
+        let _permit = Limiter::acquire(&self.limiter, &path);
+        let res = match self.http_request(Method::Head, &path, headers) {
+            Ok(res) => res,
+            Err(err) => { return Err(NetBackend::http_error("metadata", &path, err)); }
+        };
+
+        match res.status {
+            StatusCode::Ok => Ok(NetBackend::response_metadata(&res, file.as_str())),
+            StatusCode::NotFound => Err(IoError::new(
+                ErrorKind::NotFound,
+                format!("Error fetching metadata over http {}: {}", path, res.status)
+            )),
+            status => Err(IoError::new(
+                ErrorKind::Other,
+                format!("Error fetching metadata over http {}: {}", path, status)
+            ))
+        }
+    }
+
+    /// The reader streams the body as it is read.  It obeys the
+    /// bandwidth limit but not the per-host limit, since it may be left
+    /// idle with its connection open for any length of time.
+    fn open(&self, file: &AssetPath) -> IoResult<Box<ReadSeek>> {
+        if self.is_offline() {
+            let body = try!(self.offline_body(&self.url_for(file)));
+            return Ok(Box::new(io::Cursor::new(body)));
+        }
+        let mut reader = NetReader {
+            backend: self.clone(),
+            url: self.url_for(file),
+            pos: 0,
+            len: None,
+            res: None,
+        };
+        try!(reader.request());
+        Ok(Box::new(reader))
+    }
+
+    /// Asks for just the range, but copes with servers that ignore the
+    /// `Range` header and send the whole body instead.
+    fn read_range(&self, file: &AssetPath, offset: u64, len: u64) -> IoResult<Vec<u8>> {
+        if len == 0 {
+            return Ok(vec![]);
+        }
+
+        let path = self.url_for(file);
+        if self.is_offline() {
+            return self.offline_body(&path).map(|body| slice_range(&body, offset, len).to_vec());
+        }
+        let mut headers = Headers::new();
+        headers.set_raw("Accept-Encoding", vec![b"identity".to_vec()]);
+        let last = offset.saturating_add(len - 1);
+        headers.set_raw("Range", vec![format!("bytes={}-{}", offset, last).into_bytes()]);
+        let permit = Limiter::acquire(&self.limiter, &path);
+        let deadline = self.timeouts.deadline();
+        let res = match self.http_request(Method::Get, &path, headers) {
+            Ok(res) => res,
+            Err(err) => { return Err(NetBackend::http_error("file", &path, err)); }
+        };
+        let status = res.status;
+        if status == StatusCode::PartialContent {
+            try!(check_range_start(&res, offset, &path));
+        }
+        let mut res = Throttled::new(DeadlineReader::new(res, deadline, &path), permit);
+
+        let mut data = vec![];
+        match status {
+            StatusCode::PartialContent => {
+                try!(res.take(len).read_to_end(&mut data));
+            }
+            StatusCode::Ok => {
+                try!(io::copy(&mut res.by_ref().take(offset), &mut io::sink()));
+                try!(res.take(len).read_to_end(&mut data));
+            }
+            StatusCode::RangeNotSatisfiable => { }
+            StatusCode::NotFound => {
+                return Err(IoError::new(
+                    ErrorKind::NotFound,
+                    format!("Error fetching file over http {}: {}", path, status)
+                ));
+            }
+            status => {
+                return Err(IoError::new(
                     ErrorKind::Other,
-                    format!("Error fetching file over http {}: {}", path, res.status)
+                    format!("Error fetching file over http {}: {}", path, status)
                 ));
-                let mut map = mem.write().unwrap();
-                map.insert(file, error);
             }
+        }
+        Ok(data)
+    }
+
+    fn go_get(&self, file: &AssetPath, delivery: Delivery) {
+        let path = self.url_for(file);
+        let file = file.clone();
+        let backend = self.clone();
+        spawn(move || {
+            delivery.deliver(backend.download(&path, &file));
         });
     }
 }
 
+/// The first byte of a `Content-Range` header such as
+/// `bytes 100-199/200`.
+fn content_range_start(value: &str) -> Option<u64> {
+    let value = value.trim();
+    if !value.starts_with("bytes ") {
+        return None;
+    }
+    value[6..].split('-').next().and_then(|start| start.trim().parse().ok())
+}
+
+/// Make sure a partial response starts where it was asked to, rather
+/// than handing back the wrong bytes.
+fn check_range_start(res: &Response, offset: u64, path: &str) -> IoResult<()> {
+    let start = NetBackend::header(res, "Content-Range")
+        .and_then(|range| content_range_start(&range));
+    if start == Some(offset) {
+        Ok(())
+    } else {
+        Err(IoError::new(
+            ErrorKind::InvalidData,
+            format!("Error fetching file over http {}: range does not start at {}", path, offset)
+        ))
+    }
+}
+
+/// How far a `NetReader` will read ahead to satisfy a forward seek
+/// before it gives up and requests a new range instead.
+const SKIP_LIMIT: u64 = 64 * 1024;
+
+/// Streams a resource over http.  Short forward seeks skip over the
+/// body, any other seek issues a new request with a `Range` header.
+struct NetReader {
+    backend: NetBackend,
+    url: String,
+    pos: u64,
+    len: Option<u64>,
+    res: Option<Throttled<Response>>,
+}
+
+impl NetReader {
+    fn request(&mut self) -> IoResult<()> {
+        let mut headers = Headers::new();
+        headers.set_raw("Accept-Encoding", vec![b"identity".to_vec()]);
+        if self.pos > 0 {
+            headers.set_raw("Range", vec![format!("bytes={}-", self.pos).into_bytes()]);
+        }
+
+        // Let go of any earlier response first.
+        self.res = None;
+        let permit = Limiter::bandwidth_only(&self.backend.limiter);
+        let res = match self.backend.http_request(Method::Get, &self.url, headers) {
+            Ok(res) => res,
+            Err(err) => { return Err(NetBackend::http_error("file", &self.url, err)); }
+        };
+        let status = res.status;
+        let len = res.headers.get::<ContentLength>().map(|len| len.0);
+        if status == StatusCode::PartialContent {
+            try!(check_range_start(&res, self.pos, &self.url));
+        }
+        let mut res = Throttled::new(DeadlineReader::new(res, None, &self.url), permit);
+
+        match status {
+            StatusCode::PartialContent => { }
+            StatusCode::Ok => {
+                if self.len.is_none() {
+                    self.len = len;
+                }
+                // The server ignored the range, so skip up to our position.
+                try!(io::copy(&mut res.by_ref().take(self.pos), &mut io::sink()));
+            }
+            StatusCode::NotFound => {
+                return Err(IoError::new(
+                    ErrorKind::NotFound,
+                    format!("Error fetching file over http {}: {}", self.url, status)
+                ));
+            }
+            status => {
+                return Err(IoError::new(
+                    ErrorKind::Other,
+                    format!("Error fetching file over http {}: {}", self.url, status)
+                ));
+            }
+        }
+        self.res = Some(res);
+        Ok(())
+    }
+}
+
+impl Read for NetReader {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        if self.res.is_none() {
+            if self.len.map_or(false, |len| self.pos >= len) {
+                return Ok(0);
+            }
+            try!(self.request());
+        }
+
+        let n = match self.res {
+            Some(ref mut res) => try!(res.read(buf)),
+            None => 0
+        };
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for NetReader {
+    fn seek(&mut self, pos: SeekFrom) -> IoResult<u64> {
+        let target = match pos {
+            SeekFrom::Start(n) => n as i64,
+            SeekFrom::Current(n) => self.pos as i64 + n,
+            SeekFrom::End(n) => match self.len {
+                Some(len) => len as i64 + n,
+                None => {
+                    return Err(IoError::new(
+                        ErrorKind::Other,
+                        format!("Length of {} is unknown", self.url)
+                    ));
+                }
+            }
+        };
+        if target < 0 {
+            return Err(IoError::new(ErrorKind::InvalidInput, "Seek to a negative position"));
+        }
+
+        let target = target as u64;
+        if target >= self.pos && target - self.pos <= SKIP_LIMIT {
+            let skip = target - self.pos;
+            if let Some(ref mut res) = self.res {
+                self.pos += try!(io::copy(&mut res.by_ref().take(skip), &mut io::sink()));
+            }
+        }
+        if self.pos != target {
+            self.res = None;
+            self.pos = target;
+        }
+        Ok(self.pos)
+    }
+}
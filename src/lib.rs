@@ -1,18 +1,90 @@
-#![feature(path_ext)]
-
 extern crate resources_package_package;
-#[allow(plugin_as_library)]
-extern crate resources_package;
 extern crate hyper;
+extern crate time;
+extern crate flate2;
+extern crate crypto;
+extern crate rand;
+#[cfg(feature = "zstd")]
+extern crate zstd;
+
+use std::io::{Read, Seek};
+
+pub use asset_path::{
+    AssetPath,
+    Components,
+    InvalidAssetPath,
+};
 
 pub use iostore::{
     IoStore,
     FsBackend,
     NetBackend,
+    ReloadStatus,
     from_directory,
+    from_directories,
     from_url,
 };
 
+pub use metadata::AssetMetadata;
+
+pub use limits::{
+    SizeLimits,
+    TooLarge,
+};
+
+pub use compression::{
+    CompressionPolicy,
+    Encoding,
+};
+
+pub use credentials::Credentials;
+
+pub use disk_cache::DiskCache;
+
+pub use encrypted_store::{
+    EncryptedStore,
+    EncryptedStoreError,
+    encrypt,
+    decrypt,
+};
+
+pub use manifest::{
+    Manifest,
+    ManifestEntry,
+    ParseManifestError,
+    VerifiedStore,
+    VerifiedStoreError,
+};
+
+pub use zip_backend::{
+    ZipBackend,
+    ZipError,
+    from_zip,
+    from_zip_with_limits,
+};
+
+pub use pack::{
+    PackBackend,
+    PackError,
+    PackWriter,
+    from_pack,
+};
+
+pub use tar_backend::{
+    TarBackend,
+    from_tar,
+};
+
+pub use retry::{
+    RetryError,
+    RetryPolicy,
+};
+
+pub use mem_store::{
+    MemStore,
+    MemStoreError,
+};
+
 // pub use multi_store::{
 //     MultiStore,
 //     MultiStoreError,
@@ -22,13 +94,44 @@ pub use static_store::{
     StaticStoreError
 };
 
+pub use throttle::Throttle;
+
+pub use timeouts::Timeouts;
+
+pub use variant_store::VariantStore;
+
+mod asset_path;
+mod compression;
+mod credentials;
+mod disk_cache;
+mod encrypted_store;
 mod multi_store;
 mod iostore;
+mod limits;
+mod manifest;
+mod mem_store;
+mod metadata;
+mod pack;
+mod retry;
 mod static_store;
+mod tar_backend;
+mod throttle;
+mod timeouts;
+mod variant_store;
+mod zip_backend;
+
+#[cfg(test)]
+mod test;
 
-// #[cfg(test)]
-// mod test;
+/// A resource opened for streaming, as returned by `AssetStore::open`.
+pub trait ReadSeek: Read + Seek + Send {}
 
+impl <T: Read + Seek + Send> ReadSeek for T {}
+
+/// Paths are normalized with `AssetPath::new` first, which makes
+/// `a/./b.png` and `a/b.png` the same resource and refuses paths that
+/// leave the store.  See `AssetStoreExt` for taking paths as anything
+/// that converts into an `AssetPath`.
 pub trait AssetStore<E> {
     /// Tell the asset store to begin loading a resource.
     fn load(&self, path: &str);
@@ -65,6 +168,20 @@ pub trait AssetStore<E> {
         }
     }
 
+    /// Look up the size, modification time and type of a resource
+    /// without loading it.
+    fn metadata(&self, path: &str) -> Result<AssetMetadata, E>;
+
+    /// Open a resource for streaming.  The bytes are read straight
+    /// from the source and never enter the store, which suits large
+    /// files that are consumed incrementally.
+    fn open(&self, path: &str) -> Result<Box<ReadSeek>, E>;
+
+    /// Read at most `len` bytes of a resource starting at `offset`.
+    /// Less is returned if the resource ends first.  Resources that are
+    /// already loaded are sliced, otherwise only the range is fetched.
+    fn read_range(&self, path: &str, offset: u64, len: u64) -> Result<Vec<u8>, E>;
+
     /// Remove this resouce from this asset store if it is loaded.
     fn unload(&self, path: &str);
     /// Remove all these resouces from this asset store if they
@@ -112,4 +229,67 @@ pub trait AssetStore<E> {
     }
 }
 
+/// Takes paths as anything that converts into an `AssetPath`, such as
+/// an `AssetPath` built with `join`, or a `String`.  Implemented for
+/// every store, boxed ones included.
+///
+/// ```ignore
+/// let dir = AssetPath::new("textures").unwrap();
+/// store.load_path(dir.join("grass.png"));
+/// ```
+pub trait AssetStoreExt<E>: AssetStore<E> {
+    fn load_path<P: Into<AssetPath>>(&self, path: P) {
+        self.load(path.into().as_str())
+    }
+
+    fn is_path_loaded<P: Into<AssetPath>>(&self, path: P) -> Result<bool, E> {
+        self.is_loaded(path.into().as_str())
+    }
+
+    fn path_metadata<P: Into<AssetPath>>(&self, path: P) -> Result<AssetMetadata, E> {
+        self.metadata(path.into().as_str())
+    }
+
+    fn open_path<P: Into<AssetPath>>(&self, path: P) -> Result<Box<ReadSeek>, E> {
+        self.open(path.into().as_str())
+    }
+
+    fn read_path_range<P: Into<AssetPath>>(&self, path: P, offset: u64, len: u64)
+        -> Result<Vec<u8>, E> {
+        self.read_range(path.into().as_str(), offset, len)
+    }
+
+    fn unload_path<P: Into<AssetPath>>(&self, path: P) {
+        self.unload(path.into().as_str())
+    }
+
+    fn map_path<P, O, F>(&self, path: P, mapfn: F) -> Result<Option<O>, E>
+        where P: Into<AssetPath>, F: Fn(&[u8]) -> O, Self: Sized {
+        self.map_resource(path.into().as_str(), mapfn)
+    }
+
+    fn map_path_block<P, O, F>(&self, path: P, mapfn: F) -> Result<O, E>
+        where P: Into<AssetPath>, F: Fn(&[u8]) -> O, Self: Sized {
+        self.map_resource_block(path.into().as_str(), mapfn)
+    }
+}
+
+impl <S: AssetStore<E> + ?Sized, E> AssetStoreExt<E> for S {}
 
+pub trait AssetWriter<E> {
+    /// Write `bytes` to the resource at `path`, replacing whatever
+    /// was there before.
+    fn save(&self, path: &str, bytes: &[u8]) -> Result<(), E>;
+    /// Delete the resource at `path`.
+    fn remove(&self, path: &str) -> Result<(), E>;
+    /// Move the resource at `from` to `to`, replacing anything that
+    /// was already at `to`.
+    fn rename(&self, from: &str, to: &str) -> Result<(), E>;
+}
+
+/// The part of `bytes` covered by a `read_range` request.
+fn slice_range(bytes: &[u8], offset: u64, len: u64) -> &[u8] {
+    let start = if offset > bytes.len() as u64 { bytes.len() } else { offset as usize };
+    let end = if len > (bytes.len() - start) as u64 { bytes.len() } else { start + len as usize };
+    &bytes[start..end]
+}
@@ -0,0 +1,133 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, ErrorKind, Read, Write};
+
+use std::io::Error as IoError;
+use std::io::Result as IoResult;
+
+/// A resource was bigger than the limit set for it.
+#[derive(Clone, Debug)]
+pub struct TooLarge {
+    pub path: String,
+    pub limit: u64,
+    /// The size that was seen.  When the size was not known up front
+    /// this is only how far reading got before it was stopped.
+    pub size: u64,
+}
+
+impl fmt::Display for TooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} is too large: {} bytes is over the limit of {} bytes",
+               self.path, self.size, self.limit)
+    }
+}
+
+impl Error for TooLarge {
+    fn description(&self) -> &str {
+        "resource is too large"
+    }
+}
+
+/// Matches `path` against a glob where `*` matches any run of
+/// characters and `?` matches one character.
+fn glob_matches(pattern: &[char], path: &[char]) -> bool {
+    match (pattern.first(), path.first()) {
+        (None, None) => true,
+        (Some(&'*'), _) =>
+            glob_matches(&pattern[1..], path) ||
+            (!path.is_empty() && glob_matches(pattern, &path[1..])),
+        (Some(&'?'), Some(_)) => glob_matches(&pattern[1..], &path[1..]),
+        (Some(p), Some(c)) if p == c => glob_matches(&pattern[1..], &path[1..]),
+        _ => false
+    }
+}
+
+/// Maximum sizes for the resources of a store, so that a misnamed
+/// huge file or a hostile server can't exhaust memory.
+///
+/// ```ignore
+/// let mut limits = SizeLimits::new();
+/// limits.set_global(16 << 20);
+/// limits.set("*.ogg", 256 << 20);
+/// let store = from_directory("./assets/").size_limits(limits);
+/// ```
+#[derive(Clone, Debug)]
+pub struct SizeLimits {
+    global: Option<u64>,
+    patterns: Vec<(Vec<char>, u64)>,
+}
+
+impl SizeLimits {
+    /// No limits at all.
+    pub fn new() -> SizeLimits {
+        SizeLimits { global: None, patterns: vec![] }
+    }
+
+    /// The limit for resources that match no pattern.
+    pub fn set_global(&mut self, limit: u64) {
+        self.global = Some(limit);
+    }
+
+    /// The limit for resources whose path matches the glob `pattern`,
+    /// such as `*.png` or `video/*`.  The first matching pattern wins.
+    pub fn set(&mut self, pattern: &str, limit: u64) {
+        self.patterns.push((pattern.chars().collect(), limit));
+    }
+
+    pub fn limit_for(&self, path: &str) -> Option<u64> {
+        let path: Vec<char> = path.chars().collect();
+        self.patterns.iter()
+            .find(|&&(ref pattern, _)| glob_matches(pattern, &path))
+            .map(|&(_, limit)| limit)
+            .or(self.global)
+    }
+
+    /// Check a size that is known before reading, such as from file
+    /// metadata or a `Content-Length` header.
+    pub fn check(&self, path: &str, size: u64) -> IoResult<()> {
+        match self.limit_for(path) {
+            Some(limit) if size > limit => Err(IoError::new(
+                ErrorKind::Other,
+                TooLarge { path: path.to_string(), limit: limit, size: size }
+            )),
+            _ => Ok(())
+        }
+    }
+
+    /// Read all of `r`, stopping as soon as the limit is passed.
+    pub fn read_to_end<R: Read>(&self, path: &str, r: R) -> IoResult<Vec<u8>> {
+        let mut buf = vec![];
+        match self.limit_for(path) {
+            Some(limit) => {
+                try!(r.take(limit.saturating_add(1)).read_to_end(&mut buf));
+                try!(self.check(path, buf.len() as u64));
+            }
+            None => {
+                let mut r = r;
+                try!(r.read_to_end(&mut buf));
+            }
+        }
+        Ok(buf)
+    }
+
+    /// Copy all of `r` into `w`, stopping as soon as the limit is
+    /// passed.  `already` bytes of the resource were written before,
+    /// such as by a download that is being resumed.  Returns the number
+    /// of bytes copied by this call.
+    pub fn copy<R: Read, W: Write>(&self, path: &str, r: R, w: &mut W, already: u64)
+        -> IoResult<u64> {
+
+        match self.limit_for(path) {
+            Some(limit) => {
+                let room = limit.saturating_sub(already).saturating_add(1);
+                let copied = try!(io::copy(&mut r.take(room), w));
+                try!(self.check(path, already + copied));
+                Ok(copied)
+            }
+            None => {
+                let mut r = r;
+                io::copy(&mut r, w)
+            }
+        }
+    }
+}
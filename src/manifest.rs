@@ -0,0 +1,229 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::Cursor;
+use std::marker::PhantomData;
+
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+
+use super::{AssetStore, AssetMetadata, ReadSeek, slice_range};
+use asset_path::AssetPath;
+use self::VerifiedStoreError::*;
+
+/// The expected digest and size of one resource.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub sha256: [u8; 32],
+    pub size: u64,
+}
+
+impl ManifestEntry {
+    pub fn of(bytes: &[u8]) -> ManifestEntry {
+        let mut hasher = Sha256::new();
+        hasher.input(bytes);
+        let mut sha256 = [0; 32];
+        hasher.result(&mut sha256);
+        ManifestEntry { sha256: sha256, size: bytes.len() as u64 }
+    }
+
+    pub fn hex(&self) -> String {
+        self.sha256.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseManifestError {
+    /// The line that could not be parsed, counting from 1.
+    pub line: usize,
+}
+
+/// A list of resources and the digests they are expected to have.
+///
+/// The text form has one resource per line, as the hex SHA-256
+/// digest, the size in bytes and the path separated by spaces.
+#[derive(Clone, Debug)]
+pub struct Manifest {
+    entries: HashMap<AssetPath, ManifestEntry>,
+}
+
+impl Manifest {
+    pub fn new() -> Manifest {
+        Manifest { entries: HashMap::new() }
+    }
+
+    pub fn parse(text: &str) -> Result<Manifest, ParseManifestError> {
+        let mut manifest = Manifest::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = ParseManifestError { line: i + 1 };
+            let mut parts = line.splitn(3, ' ');
+            let (digest, size, path) = match (parts.next(), parts.next(), parts.next()) {
+                (Some(digest), Some(size), Some(path)) => (digest, size, path),
+                _ => { return Err(error); }
+            };
+            if digest.len() != 64 || !digest.chars().all(|c| c.is_digit(16)) {
+                return Err(error);
+            }
+
+            let mut sha256 = [0; 32];
+            for (j, byte) in sha256.iter_mut().enumerate() {
+                *byte = match u8::from_str_radix(&digest[j * 2..j * 2 + 2], 16) {
+                    Ok(byte) => byte,
+                    Err(_) => { return Err(error); }
+                };
+            }
+            let size = match size.parse() {
+                Ok(size) => size,
+                Err(_) => { return Err(error); }
+            };
+            manifest.insert(path, ManifestEntry { sha256: sha256, size: size });
+        }
+        Ok(manifest)
+    }
+
+    pub fn insert<P: Into<AssetPath>>(&mut self, path: P, entry: ManifestEntry) {
+        self.entries.insert(path.into(), entry);
+    }
+
+    /// Record the digest of `bytes` as the expected contents of `path`.
+    pub fn add<P: Into<AssetPath>>(&mut self, path: P, bytes: &[u8]) {
+        self.insert(path, ManifestEntry::of(bytes));
+    }
+
+    pub fn get<P: Into<AssetPath>>(&self, path: P) -> Option<&ManifestEntry> {
+        self.entries.get(&path.into())
+    }
+}
+
+impl fmt::Display for Manifest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sorted: BTreeMap<&AssetPath, &ManifestEntry> = self.entries.iter().collect();
+        for (path, entry) in sorted.into_iter() {
+            try!(writeln!(f, "{} {} {}", entry.hex(), entry.size, path));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum VerifiedStoreError<E> {
+    /// The resource did not match the manifest.
+    Corrupt {
+        path: String,
+        expected: ManifestEntry,
+        actual: ManifestEntry,
+    },
+    WrappedError(E)
+}
+
+/// Wraps another store and checks every resource against a manifest
+/// before it is served, which catches truncated downloads and disk
+/// corruption.  Resources that are not in the manifest are served
+/// without being checked.
+///
+/// The wrapped store can replace a resource at any time, so resources
+/// are hashed every time they are served.  Map a large resource once
+/// and keep the result rather than mapping it over and over.  After a
+/// `Corrupt` error, unload the resource to try loading it again.
+pub struct VerifiedStore<S, E> {
+    store: S,
+    manifest: Manifest,
+    _e: PhantomData<*const E>
+}
+
+impl <S: AssetStore<E>, E> VerifiedStore<S, E> {
+    pub fn new(store: S, manifest: Manifest) -> VerifiedStore<S, E> {
+        VerifiedStore {
+            store: store,
+            manifest: manifest,
+            _e: PhantomData
+        }
+    }
+
+    /// Checks `bytes` against the manifest.
+    fn check(&self, path: &AssetPath, bytes: &[u8]) -> Result<(), VerifiedStoreError<E>> {
+        let expected = match self.manifest.get(path) {
+            Some(expected) => expected,
+            None => { return Ok(()); }
+        };
+
+        let actual = ManifestEntry::of(bytes);
+        if actual == *expected {
+            Ok(())
+        } else {
+            Err(Corrupt {
+                path: path.to_string(),
+                expected: expected.clone(),
+                actual: actual,
+            })
+        }
+    }
+}
+
+impl <S: AssetStore<E>, E> AssetStore<VerifiedStoreError<E>> for VerifiedStore<S, E> {
+    fn load(&self, path: &str) {
+        self.store.load(path);
+    }
+
+    fn is_loaded(&self, path: &str) -> Result<bool, VerifiedStoreError<E>> {
+        self.map_resource(path, |_| ()).map(|x| x.is_some())
+    }
+
+    fn metadata(&self, path: &str) -> Result<AssetMetadata, VerifiedStoreError<E>> {
+        self.store.metadata(path).map_err(WrappedError)
+    }
+
+    /// Resources in the manifest have to be read completely to be
+    /// checked, so they are loaded before the reader is returned.
+    fn open(&self, path: &str) -> Result<Box<ReadSeek>, VerifiedStoreError<E>> {
+        if self.manifest.get(path).is_none() {
+            return self.store.open(path).map_err(WrappedError);
+        }
+        self.map_resource_block(path, |x| {
+            Box::new(Cursor::new(x.to_vec())) as Box<ReadSeek>
+        })
+    }
+
+    fn read_range(&self, path: &str, offset: u64, len: u64)
+        -> Result<Vec<u8>, VerifiedStoreError<E>> {
+
+        if self.manifest.get(path).is_none() {
+            return self.store.read_range(path, offset, len).map_err(WrappedError);
+        }
+        self.map_resource_block(path, |x| slice_range(x, offset, len).to_vec())
+    }
+
+    fn unload(&self, path: &str) {
+        self.store.unload(path);
+    }
+
+    fn unload_everything(&self) {
+        self.store.unload_everything();
+    }
+
+    fn map_resource<O, F>(&self , path: &str, mapfn: F) -> Result<Option<O>, VerifiedStoreError<E>>
+        where F: Fn(&[u8]) -> O {
+
+        let key = AssetPath::from(path);
+        let out = try!(self.store.map_resource(path, |x| {
+            self.check(&key, x).map(|_| mapfn(x))
+        }).map_err(WrappedError));
+        match out {
+            Some(Ok(out)) => Ok(Some(out)),
+            Some(Err(e)) => Err(e),
+            None => Ok(None)
+        }
+    }
+
+    fn map_resource_block<O, F>(&self, path: &str, mapfn: F) -> Result<O, VerifiedStoreError<E>>
+        where F: Fn(&[u8]) -> O {
+
+        let key = AssetPath::from(path);
+        self.store.map_resource_block(path, |x| {
+            self.check(&key, x).map(|_| mapfn(x))
+        }).map_err(WrappedError).and_then(|out| out)
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::RwLock;
+
+use super::{AssetStore, AssetWriter, AssetMetadata, ReadSeek, slice_range};
+use asset_path::{AssetPath, InvalidAssetPath};
+use metadata::guess_content_type;
+
+#[derive(Debug)]
+pub enum MemStoreError {
+    NotFound(String),
+    /// The path leaves the store.
+    InvalidPath(InvalidAssetPath),
+    Poisoned
+}
+
+impl From<InvalidAssetPath> for MemStoreError {
+    fn from(e: InvalidAssetPath) -> MemStoreError {
+        MemStoreError::InvalidPath(e)
+    }
+}
+
+fn asset_path(path: &str) -> Result<AssetPath, MemStoreError> {
+    AssetPath::new(path).map_err(MemStoreError::InvalidPath)
+}
+
+/// A store that keeps every resource in memory.  Resources only
+/// exist once they have been written with `AssetWriter::save`.
+pub struct MemStore {
+    mem: RwLock<HashMap<AssetPath, Vec<u8>>>,
+}
+
+impl MemStore {
+    pub fn new() -> MemStore {
+        MemStore { mem: RwLock::new(HashMap::new()) }
+    }
+}
+
+impl AssetStore<MemStoreError> for MemStore {
+    fn load(&self, _: &str) { }
+
+    fn is_loaded(&self, path: &str) -> Result<bool, MemStoreError> {
+        let path = try!(asset_path(path));
+        match self.mem.read() {
+            Ok(mem) => Ok(mem.contains_key(&path)),
+            Err(_) => Err(MemStoreError::Poisoned)
+        }
+    }
+
+    fn metadata(&self, path: &str) -> Result<AssetMetadata, MemStoreError> {
+        self.map_resource_block(path, |x| {
+            let mut meta = AssetMetadata::new();
+            meta.size = Some(x.len() as u64);
+            meta.content_type = guess_content_type(path);
+            meta
+        })
+    }
+
+    /// The reader works on a copy, so later saves do not affect it.
+    fn open(&self, path: &str) -> Result<Box<ReadSeek>, MemStoreError> {
+        self.map_resource_block(path, |x| {
+            Box::new(Cursor::new(x.to_vec())) as Box<ReadSeek>
+        })
+    }
+
+    fn read_range(&self, path: &str, offset: u64, len: u64) -> Result<Vec<u8>, MemStoreError> {
+        self.map_resource_block(path, |x| slice_range(x, offset, len).to_vec())
+    }
+
+    fn unload(&self, _: &str) { }
+
+    fn unload_everything(&self) { }
+
+    fn map_resource<O, F>(&self , path: &str, mapfn: F) -> Result<Option<O>, MemStoreError>
+        where F : Fn(&[u8]) -> O {
+
+        let path = try!(asset_path(path));
+        let mem = match self.mem.read() {
+            Ok(mem) => mem,
+            Err(_) => { return Err(MemStoreError::Poisoned); }
+        };
+
+        match mem.get(&path) {
+            Some(x) => Ok(Some(mapfn(&x[..]))),
+            None => Err(MemStoreError::NotFound(path.to_string()))
+        }
+    }
+
+    fn map_resource_block<O, F>(&self, path: &str, mapfn: F) -> Result<O, MemStoreError>
+        where F : Fn(&[u8]) -> O {
+
+        match self.map_resource(path, mapfn) {
+            Ok(Some(x)) => Ok(x),
+            Ok(None) => unreachable!(),
+            Err(x) => Err(x)
+        }
+    }
+}
+
+impl AssetWriter<MemStoreError> for MemStore {
+    fn save(&self, path: &str, bytes: &[u8]) -> Result<(), MemStoreError> {
+        let path = try!(asset_path(path));
+        match self.mem.write() {
+            Ok(mut mem) => {
+                mem.insert(path, bytes.to_vec());
+                Ok(())
+            }
+            Err(_) => Err(MemStoreError::Poisoned)
+        }
+    }
+
+    fn remove(&self, path: &str) -> Result<(), MemStoreError> {
+        let path = try!(asset_path(path));
+        match self.mem.write() {
+            Ok(mut mem) => match mem.remove(&path) {
+                Some(_) => Ok(()),
+                None => Err(MemStoreError::NotFound(path.to_string()))
+            },
+            Err(_) => Err(MemStoreError::Poisoned)
+        }
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<(), MemStoreError> {
+        let (from, to) = (try!(asset_path(from)), try!(asset_path(to)));
+        match self.mem.write() {
+            Ok(mut mem) => match mem.remove(&from) {
+                Some(bytes) => {
+                    mem.insert(to, bytes);
+                    Ok(())
+                }
+                None => Err(MemStoreError::NotFound(from.to_string()))
+            },
+            Err(_) => Err(MemStoreError::Poisoned)
+        }
+    }
+}
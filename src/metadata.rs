@@ -0,0 +1,64 @@
+use std::path::Path;
+use std::time::SystemTime;
+
+/// Information about a resource that can be found without loading it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssetMetadata {
+    /// Size of the resource in bytes, if the source reports it.
+    pub size: Option<u64>,
+    /// Size of the resource as it is stored, which is smaller than
+    /// `size` when the source keeps it compressed.
+    pub stored_size: Option<u64>,
+    /// When the resource was last changed.
+    pub modified: Option<SystemTime>,
+    /// The MIME type of the resource, either reported by the source
+    /// or guessed from the file extension.
+    pub content_type: Option<String>,
+    /// A content hash or HTTP entity tag that changes whenever the
+    /// contents do.
+    pub etag: Option<String>,
+}
+
+impl AssetMetadata {
+    pub fn new() -> AssetMetadata {
+        AssetMetadata {
+            size: None,
+            stored_size: None,
+            modified: None,
+            content_type: None,
+            etag: None,
+        }
+    }
+}
+
+/// Guess the MIME type of a resource from the extension of its path.
+pub fn guess_content_type(path: &str) -> Option<String> {
+    let ext = match Path::new(path).extension().and_then(|e| e.to_str()) {
+        Some(ext) => ext.to_lowercase(),
+        None => { return None; }
+    };
+
+    let mime = match &ext[..] {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "bmp" => "image/bmp",
+        "svg" => "image/svg+xml",
+        "ogg" => "audio/ogg",
+        "wav" => "audio/wav",
+        "mp3" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "zip" => "application/zip",
+        "html" | "htm" => "text/html",
+        "css" => "text/css",
+        "js" => "application/javascript",
+        "txt" => "text/plain",
+        _ => { return None; }
+    };
+    Some(mime.to_string())
+}
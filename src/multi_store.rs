@@ -1,13 +1,16 @@
 use std::collections::HashMap;
 use std::marker::PhantomData;
 
-use super::AssetStore;
+use super::{AssetStore, AssetMetadata, ReadSeek};
+use asset_path::{AssetPath, InvalidAssetPath};
 use self::MultiStoreError::*;
 
 #[derive(Debug)]
 pub enum MultiStoreError<E> {
     NoSplit,
     StoreNotFound(String),
+    /// The path leaves the store.
+    InvalidPath(InvalidAssetPath),
     WrappedError(E)
 }
 
@@ -54,6 +57,18 @@ impl <S: AssetStore<E>, E, T, F: Fn(E) -> T> AssetStore<T> for StoreWrapper<S, E
         }
     }
 
+    fn metadata(&self, path: &str) -> Result<AssetMetadata, T> {
+        self.store.metadata(path).map_err(|e| (self.trans)(e))
+    }
+
+    fn open(&self, path: &str) -> Result<Box<ReadSeek>, T> {
+        self.store.open(path).map_err(|e| (self.trans)(e))
+    }
+
+    fn read_range(&self, path: &str, offset: u64, len: u64) -> Result<Vec<u8>, T> {
+        self.store.read_range(path, offset, len).map_err(|e| (self.trans)(e))
+    }
+
     fn unload(&self, path: &str) {
         self.store.unload(path);
     }
@@ -98,15 +113,17 @@ impl<'a, T: 'a> MultiStore<'a, T> {
         self.stores.insert(prefix.to_string(), Box::new(wrapped));
     }
 
-    fn get_store<'b>(&self, path: &'b str) ->
-    Result<(&Box<AssetStore<T>>, &'b str), MultiStoreError<T>> {
-        let split: Vec<&str> = path.splitn(1, ':').collect();
-        if split.len() == 1 {
-            return Err(NoSplit)
-        }
-        let (before, after) = (split[0], split[1]);
-        match self.stores.get(&before.to_string()) {
-            Some(x) => Ok((x, after)),
+    /// Splits `prefix:path` at the first `:` and finds the store
+    /// registered under `prefix`.
+    fn get_store(&self, path: &str) ->
+    Result<(&Box<AssetStore<T> + 'a>, AssetPath), MultiStoreError<T>> {
+        let (before, after) = match path.find(':') {
+            Some(i) => (&path[..i], &path[i + 1..]),
+            None => { return Err(NoSplit); }
+        };
+        let path = try!(AssetPath::new(after).map_err(InvalidPath));
+        match self.stores.get(before) {
+            Some(x) => Ok((x, path)),
             None => Err(StoreNotFound(before.to_string()))
         }
     }
@@ -0,0 +1,450 @@
+//! A simple indexed archive format, built for opening many small
+//! resources through one file handle.
+//!
+//! All integers are little endian.  A pack is laid out as
+//!
+//! ```text
+//! header   magic "ASTPACK\0", version u32, entry count u32,
+//!          data offset u64, reserved u64
+//! index    per entry, sorted by path: path length u16, path bytes,
+//!          offset u64, stored length u64, decoded length u64,
+//!          FNV-1a hash of the stored bytes u64, flags u32
+//! data     each entry starts on a 16 byte boundary
+//! ```
+//!
+//! The only flags are the encoding of the entry, 1 for gzip and 2 for
+//! zstd.  Version 1 packs have no decoded length and no flags set.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{Cursor, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use std::io::Error as IoError;
+use std::io::Result as IoResult;
+
+use asset_path::{AssetPath, InvalidAssetPath};
+use iostore::{IoStore, IoBackend, ListBackend, Delivery, check_sandboxed};
+use metadata::{AssetMetadata, guess_content_type};
+use compression::Encoding;
+use super::{AssetStore, ReadSeek, slice_range};
+
+const MAGIC: &'static [u8; 8] = b"ASTPACK\0";
+const VERSION: u32 = 2;
+const HEADER_LEN: u64 = 32;
+const ALIGN: u64 = 16;
+
+const FLAG_GZIP: u32 = 1;
+const FLAG_ZSTD: u32 = 2;
+
+fn flag_encoding(flags: u32) -> Option<Encoding> {
+    match flags {
+        FLAG_GZIP => Some(Encoding::Gzip),
+        FLAG_ZSTD => Some(Encoding::Zstd),
+        _ => None
+    }
+}
+
+fn encoding_flag(encoding: Encoding) -> u32 {
+    match encoding {
+        Encoding::Gzip => FLAG_GZIP,
+        Encoding::Zstd => FLAG_ZSTD,
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum PackError {
+    /// The file does not start with the pack magic number.
+    BadMagic,
+    /// The pack was written by a newer or older version of the format.
+    UnsupportedVersion(u32),
+    /// An entry uses flags that this version does not understand.
+    UnsupportedFlags(String, u32),
+    /// The file ends before the header or index does.
+    Truncated,
+    /// The index is out of order or points outside of the file.
+    CorruptIndex(&'static str),
+    /// An entry did not match the hash recorded in the index.
+    BadHash(String),
+    /// An entry did not decode to the size recorded in the index.
+    BadSize(String),
+}
+
+impl fmt::Display for PackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PackError::BadMagic => write!(f, "Not a pack file"),
+            PackError::UnsupportedVersion(v) =>
+                write!(f, "Unsupported pack version {}, expected {}", v, VERSION),
+            PackError::UnsupportedFlags(ref path, flags) =>
+                write!(f, "Unsupported flags {:x} on pack entry {}", flags, path),
+            PackError::Truncated => write!(f, "Pack file is truncated"),
+            PackError::CorruptIndex(why) => write!(f, "Corrupt pack index: {}", why),
+            PackError::BadHash(ref path) => write!(f, "Bad hash for pack entry {}", path),
+            PackError::BadSize(ref path) => write!(f, "Bad size for pack entry {}", path),
+        }
+    }
+}
+
+impl Error for PackError {
+    fn description(&self) -> &str {
+        match *self {
+            PackError::BadMagic => "not a pack file",
+            PackError::UnsupportedVersion(_) => "unsupported pack version",
+            PackError::UnsupportedFlags(..) => "unsupported pack entry flags",
+            PackError::Truncated => "pack file is truncated",
+            PackError::CorruptIndex(_) => "corrupt pack index",
+            PackError::BadHash(_) => "bad hash in pack entry",
+            PackError::BadSize(_) => "bad size in pack entry",
+        }
+    }
+}
+
+fn pack_error(err: PackError) -> IoError {
+    IoError::new(ErrorKind::InvalidData, err)
+}
+
+/// 64 bit FNV-1a, used to catch corrupted entries.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash = 0xcbf29ce484222325;
+    for &b in bytes.iter() {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+fn align(n: u64) -> u64 {
+    (n + ALIGN - 1) / ALIGN * ALIGN
+}
+
+fn read_bytes<R: Read>(r: &mut R, n: usize) -> IoResult<Vec<u8>> {
+    let mut buf = vec![0; n];
+    match r.read_exact(&mut buf) {
+        Ok(()) => Ok(buf),
+        Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => Err(pack_error(PackError::Truncated)),
+        Err(e) => Err(e)
+    }
+}
+
+fn read_uint<R: Read>(r: &mut R, n: usize) -> IoResult<u64> {
+    let buf = try!(read_bytes(r, n));
+    Ok(buf.iter().rev().fold(0, |acc, &b| (acc << 8) | b as u64))
+}
+
+fn write_uint<W: Write>(w: &mut W, n: usize, value: u64) -> IoResult<()> {
+    let bytes: Vec<u8> = (0..n).map(|i| (value >> (8 * i)) as u8).collect();
+    w.write_all(&bytes)
+}
+
+#[derive(Clone, Debug)]
+struct PackEntry {
+    path: String,
+    offset: u64,
+    /// Length of the entry as stored in the pack.
+    len: u64,
+    /// Length of the entry once decoded.
+    size: u64,
+    hash: u64,
+    flags: u32,
+}
+
+/// Serves resources out of a pack file through a single shared
+/// file handle.
+#[derive(Clone)]
+pub struct PackBackend {
+    path: PathBuf,
+    file: Arc<Mutex<File>>,
+    entries: Arc<Vec<PackEntry>>,
+}
+
+pub fn from_pack(path: &str) -> IoResult<IoStore<PackBackend>> {
+    let path = PathBuf::from(String::from(path));
+    let mut file = try!(File::open(&path));
+    let entries = try!(read_index(&mut file));
+    Ok(IoStore::new(PackBackend {
+        path: path,
+        file: Arc::new(Mutex::new(file)),
+        entries: Arc::new(entries),
+    }))
+}
+
+fn read_index(file: &mut File) -> IoResult<Vec<PackEntry>> {
+    let file_len = try!(file.seek(SeekFrom::End(0)));
+    try!(file.seek(SeekFrom::Start(0)));
+
+    if &try!(read_bytes(file, 8))[..] != &MAGIC[..] {
+        return Err(pack_error(PackError::BadMagic));
+    }
+    let version = try!(read_uint(file, 4)) as u32;
+    if version != 1 && version != VERSION {
+        return Err(pack_error(PackError::UnsupportedVersion(version)));
+    }
+    let count = try!(read_uint(file, 4));
+    let data_offset = try!(read_uint(file, 8));
+    try!(read_uint(file, 8));
+
+    let mut entries: Vec<PackEntry> = vec![];
+    for _ in 0..count {
+        let path_len = try!(read_uint(file, 2)) as usize;
+        let path = match String::from_utf8(try!(read_bytes(file, path_len))) {
+            Ok(path) => path,
+            Err(_) => { return Err(pack_error(PackError::CorruptIndex("path is not utf-8"))); }
+        };
+        let offset = try!(read_uint(file, 8));
+        let len = try!(read_uint(file, 8));
+        let size = if version == 1 { len } else { try!(read_uint(file, 8)) };
+        let entry = PackEntry {
+            path: path,
+            offset: offset,
+            len: len,
+            size: size,
+            hash: try!(read_uint(file, 8)),
+            flags: try!(read_uint(file, 4)) as u32,
+        };
+
+        if entries.last().map_or(false, |last| last.path >= entry.path) {
+            return Err(pack_error(PackError::CorruptIndex("paths are not sorted")));
+        }
+        if entry.offset < data_offset ||
+            entry.offset.checked_add(entry.len).map_or(true, |end| end > file_len) {
+            return Err(pack_error(PackError::CorruptIndex("entry lies outside of the file")));
+        }
+        // Version 1 had no compressed entries, so any flag is an error.
+        if entry.flags != 0 && (version == 1 || flag_encoding(entry.flags).is_none()) {
+            return Err(pack_error(PackError::UnsupportedFlags(entry.path, entry.flags)));
+        }
+        entries.push(entry);
+    }
+    if data_offset < try!(file.seek(SeekFrom::Current(0))) {
+        return Err(pack_error(PackError::CorruptIndex("data overlaps the index")));
+    }
+    Ok(entries)
+}
+
+impl PackBackend {
+    fn entry(&self, file: &AssetPath) -> IoResult<&PackEntry> {
+        try!(check_sandboxed(file.as_str()));
+        match self.entries.binary_search_by(|entry| entry.path[..].cmp(file.as_str())) {
+            Ok(i) => Ok(&self.entries[i]),
+            Err(_) => Err(IoError::new(
+                ErrorKind::NotFound,
+                format!("Given path does not exist: {} does not contain {}",
+                        self.path.display(), file)
+            ))
+        }
+    }
+
+    fn read_at(&self, offset: u64, len: u64) -> IoResult<Vec<u8>> {
+        let mut file = match self.file.lock() {
+            Ok(file) => file,
+            Err(_) => { return Err(IoError::new(ErrorKind::Other, "Poisoned thread")); }
+        };
+        try!(file.seek(SeekFrom::Start(offset)));
+        read_bytes(&mut *file, len as usize)
+    }
+
+    fn extract(&self, file: &AssetPath) -> IoResult<Vec<u8>> {
+        let entry = try!(self.entry(file));
+        let data = try!(self.read_at(entry.offset, entry.len));
+        if fnv1a(&data) != entry.hash {
+            return Err(pack_error(PackError::BadHash(file.to_string())));
+        }
+        let data = match flag_encoding(entry.flags) {
+            Some(encoding) => {
+                // Stop just past the recorded size, so a bad entry can't
+                // decode to much more than it claims.
+                let mut decoded = vec![];
+                let decoder = try!(encoding.decoder(&data[..]));
+                try!(decoder.take(entry.size.saturating_add(1)).read_to_end(&mut decoded));
+                decoded
+            }
+            None => data
+        };
+        if data.len() as u64 != entry.size {
+            return Err(pack_error(PackError::BadSize(file.to_string())));
+        }
+        Ok(data)
+    }
+}
+
+/// A window onto one entry, sharing the pack's file handle.
+struct PackMember {
+    backend: PackBackend,
+    start: u64,
+    len: u64,
+    pos: u64,
+}
+
+impl Read for PackMember {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let left = self.len.saturating_sub(self.pos);
+        let want = if left < buf.len() as u64 { left as usize } else { buf.len() };
+        let data = try!(self.backend.read_at(self.start + self.pos, want as u64));
+        buf[..want].clone_from_slice(&data);
+        self.pos += want as u64;
+        Ok(want)
+    }
+}
+
+impl Seek for PackMember {
+    fn seek(&mut self, pos: SeekFrom) -> IoResult<u64> {
+        let target = match pos {
+            SeekFrom::Start(n) => n as i64,
+            SeekFrom::Current(n) => self.pos as i64 + n,
+            SeekFrom::End(n) => self.len as i64 + n,
+        };
+        if target < 0 {
+            return Err(IoError::new(ErrorKind::InvalidInput, "Seek to a negative position"));
+        }
+        self.pos = target as u64;
+        Ok(self.pos)
+    }
+}
+
+impl IoBackend for PackBackend {
+    fn go_get(&self, file: &AssetPath, delivery: Delivery) {
+        let backend = self.clone();
+        let file = file.clone();
+        thread::spawn(move || {
+            delivery.deliver(backend.extract(&file));
+        });
+    }
+
+    fn metadata(&self, file: &AssetPath) -> IoResult<AssetMetadata> {
+        let entry = try!(self.entry(file));
+        let mut meta = AssetMetadata::new();
+        meta.size = Some(entry.size);
+        meta.stored_size = Some(entry.len);
+        meta.content_type = guess_content_type(file.as_str());
+        meta.etag = Some(format!("{:016x}", entry.hash));
+        Ok(meta)
+    }
+
+    /// Compressed entries are decoded up front, anything else is read
+    /// from the pack as it is consumed.
+    fn open(&self, file: &AssetPath) -> IoResult<Box<ReadSeek>> {
+        let entry = try!(self.entry(file));
+        if entry.flags != 0 {
+            return Ok(Box::new(Cursor::new(try!(self.extract(file)))));
+        }
+        Ok(Box::new(PackMember {
+            backend: self.clone(),
+            start: entry.offset,
+            len: entry.len,
+            pos: 0,
+        }))
+    }
+
+    fn read_range(&self, file: &AssetPath, offset: u64, len: u64) -> IoResult<Vec<u8>> {
+        let entry = try!(self.entry(file));
+        if entry.flags != 0 {
+            let data = try!(self.extract(file));
+            return Ok(slice_range(&data, offset, len).to_vec());
+        }
+        let offset = if offset > entry.len { entry.len } else { offset };
+        let len = if len > entry.len - offset { entry.len - offset } else { len };
+        self.read_at(entry.offset + offset, len)
+    }
+}
+
+impl ListBackend for PackBackend {
+    fn list(&self) -> IoResult<Vec<String>> {
+        Ok(self.entries.iter().map(|entry| entry.path.clone()).collect())
+    }
+}
+
+/// Builds a pack file.
+///
+/// ```ignore
+/// let mut pack = PackWriter::new();
+/// try!(pack.add_from(&from_directory("./assets/"), paths.iter().map(|p| &p[..])));
+/// try!(pack.write(try!(File::create("assets.pack"))));
+/// ```
+pub struct PackWriter {
+    /// The stored bytes, decoded length and flags of each entry.
+    entries: BTreeMap<String, (Vec<u8>, u64, u32)>,
+}
+
+impl PackWriter {
+    pub fn new() -> PackWriter {
+        PackWriter { entries: BTreeMap::new() }
+    }
+
+    /// Add a resource, replacing any earlier resource with the same path.
+    /// Paths that leave the pack are refused.
+    pub fn add(&mut self, path: &str, bytes: &[u8]) -> Result<(), InvalidAssetPath> {
+        let path = try!(AssetPath::new(path));
+        self.entries.insert(path.to_string(), (bytes.to_vec(), bytes.len() as u64, 0));
+        Ok(())
+    }
+
+    /// Add a resource that is stored compressed and decoded when it
+    /// is loaded.
+    pub fn add_compressed(&mut self, path: &str, bytes: &[u8], encoding: Encoding)
+        -> IoResult<()> {
+
+        let path = try!(AssetPath::new(path));
+        let stored = try!(encoding.encode(bytes));
+        self.entries.insert(path.to_string(), (stored, bytes.len() as u64, encoding_flag(encoding)));
+        Ok(())
+    }
+
+    /// Copy resources out of any store, blocking until each is loaded.
+    pub fn add_from<'a, E, S, I>(&mut self, store: &S, paths: I) -> Result<(), E>
+    where S: AssetStore<E>, E: From<InvalidAssetPath>, I: Iterator<Item=&'a str> {
+        for path in paths {
+            let bytes = try!(store.map_resource_block(path, |bytes| bytes.to_vec()));
+            try!(self.add(path, &bytes));
+        }
+        Ok(())
+    }
+
+    pub fn write<W: Write>(&self, mut out: W) -> IoResult<()> {
+        for path in self.entries.keys() {
+            try!(check_sandboxed(path));
+            if path.len() > 0xffff {
+                return Err(IoError::new(
+                    ErrorKind::InvalidInput,
+                    format!("Path is too long for a pack: {}", path)
+                ));
+            }
+        }
+
+        let index_len: u64 = self.entries.keys()
+            .map(|path| 2 + path.len() as u64 + 8 + 8 + 8 + 8 + 4)
+            .fold(0, |a, b| a + b);
+        let data_offset = align(HEADER_LEN + index_len);
+
+        try!(out.write_all(MAGIC));
+        try!(write_uint(&mut out, 4, VERSION as u64));
+        try!(write_uint(&mut out, 4, self.entries.len() as u64));
+        try!(write_uint(&mut out, 8, data_offset));
+        try!(write_uint(&mut out, 8, 0));
+
+        let mut offset = data_offset;
+        for (path, &(ref bytes, size, flags)) in self.entries.iter() {
+            try!(write_uint(&mut out, 2, path.len() as u64));
+            try!(out.write_all(path.as_bytes()));
+            try!(write_uint(&mut out, 8, offset));
+            try!(write_uint(&mut out, 8, bytes.len() as u64));
+            try!(write_uint(&mut out, 8, size));
+            try!(write_uint(&mut out, 8, fnv1a(bytes)));
+            try!(write_uint(&mut out, 4, flags as u64));
+            offset = align(offset + bytes.len() as u64);
+        }
+
+        let mut written = HEADER_LEN + index_len;
+        for &(ref bytes, _, _) in self.entries.values() {
+            let padding = align(written) - written;
+            try!(out.write_all(&vec![0; padding as usize]));
+            try!(out.write_all(bytes));
+            written = align(written) + bytes.len() as u64;
+        }
+        out.flush()
+    }
+}
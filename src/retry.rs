@@ -0,0 +1,174 @@
+use std::cmp;
+use std::error::Error;
+use std::fmt;
+use std::io::ErrorKind;
+use std::thread;
+use std::time::Duration;
+
+use std::io::Error as IoError;
+use std::io::Result as IoResult;
+
+use rand;
+
+/// Every attempt at fetching a resource failed.
+#[derive(Clone, Debug)]
+pub struct RetryError {
+    pub url: String,
+    /// What went wrong on each attempt, in order.
+    pub attempts: Vec<String>,
+}
+
+impl fmt::Display for RetryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "Error fetching {} after {} attempts", self.url, self.attempts.len()));
+        for (i, attempt) in self.attempts.iter().enumerate() {
+            try!(write!(f, "; attempt {}: {}", i + 1, attempt));
+        }
+        Ok(())
+    }
+}
+
+impl Error for RetryError {
+    fn description(&self) -> &str {
+        "every attempt to fetch the resource failed"
+    }
+}
+
+/// Why one attempt failed.
+pub struct Failure {
+    pub error: IoError,
+    /// The status the server answered with, if it answered.
+    pub status: Option<u16>,
+    /// How long the server asked us to wait before trying again.
+    pub retry_after: Option<Duration>,
+}
+
+impl Failure {
+    pub fn io(error: IoError) -> Failure {
+        Failure { error: error, status: None, retry_after: None }
+    }
+}
+
+/// How often and how patiently failed downloads are tried again.
+///
+/// The delay before attempt `n + 1` is `base_delay * 2^(n - 1)`,
+/// spread by up to `jitter` of itself in either direction so that
+/// many clients don't retry in lockstep, and is never more than
+/// `max_delay`.  A `Retry-After` header from the server takes
+/// precedence, unless it asks for a wait longer than `max_delay`, in
+/// which case the download fails rather than hang for that long.
+///
+/// ```ignore
+/// let mut retry = RetryPolicy::new();
+/// retry.set_attempts(4);
+/// retry.set_base_delay(Duration::from_millis(250));
+/// let store = from_url("http://example.com/assets/").unwrap().retry(retry);
+/// ```
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    jitter: f64,
+    statuses: Vec<u16>,
+    io_kinds: Vec<ErrorKind>,
+}
+
+impl RetryPolicy {
+    /// A single attempt, with timeouts, connection failures and the
+    /// usual temporary statuses marked as retryable should more
+    /// attempts be allowed.
+    pub fn new() -> RetryPolicy {
+        RetryPolicy {
+            attempts: 1,
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(30),
+            jitter: 0.5,
+            statuses: vec![408, 429, 500, 502, 503, 504],
+            io_kinds: vec![
+                ErrorKind::ConnectionRefused,
+                ErrorKind::ConnectionReset,
+                ErrorKind::ConnectionAborted,
+                ErrorKind::BrokenPipe,
+                ErrorKind::TimedOut,
+                ErrorKind::Interrupted,
+                ErrorKind::UnexpectedEof,
+            ],
+        }
+    }
+
+    /// The most attempts made, counting the first.
+    pub fn set_attempts(&mut self, attempts: u32) {
+        self.attempts = if attempts == 0 { 1 } else { attempts };
+    }
+
+    pub fn set_base_delay(&mut self, delay: Duration) {
+        self.base_delay = delay;
+    }
+
+    /// The longest wait between attempts.  Defaults to 30 seconds.
+    pub fn set_max_delay(&mut self, delay: Duration) {
+        self.max_delay = delay;
+    }
+
+    /// The fraction of each delay that is randomized, from 0 to 1.
+    pub fn set_jitter(&mut self, jitter: f64) {
+        self.jitter = jitter.max(0.0).min(1.0);
+    }
+
+    /// Replace the statuses that are retried.
+    pub fn set_statuses(&mut self, statuses: &[u16]) {
+        self.statuses = statuses.to_vec();
+    }
+
+    /// Replace the kinds of io errors that are retried.
+    pub fn set_io_kinds(&mut self, kinds: &[ErrorKind]) {
+        self.io_kinds = kinds.to_vec();
+    }
+
+    fn is_retryable(&self, failure: &Failure) -> bool {
+        match failure.status {
+            Some(status) => self.statuses.contains(&status),
+            None => self.io_kinds.contains(&failure.error.kind())
+        }
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        let base = self.base_delay.as_secs() as f64 * 1000.0 +
+                   self.base_delay.subsec_nanos() as f64 / 1_000_000.0;
+        let spread = 1.0 + self.jitter * (rand::random::<f64>() * 2.0 - 1.0);
+        let ms = base * (1u64 << cmp::min(attempt - 1, 20)) as f64 * spread;
+        cmp::min(Duration::from_millis(ms as u64), self.max_delay)
+    }
+
+    /// Run `fetch` until it succeeds, fails in a way that is not
+    /// retryable, or runs out of attempts.  A lone failure is returned
+    /// as it is, otherwise a `RetryError` lists every attempt.
+    pub fn run<T, F>(&self, url: &str, mut fetch: F) -> IoResult<T>
+    where F: FnMut() -> Result<T, Failure> {
+        let mut errors: Vec<IoError> = vec![];
+        for attempt in 1..self.attempts + 1 {
+            let failure = match fetch() {
+                Ok(out) => { return Ok(out); }
+                Err(failure) => failure
+            };
+            let delay = failure.retry_after.unwrap_or_else(|| self.backoff(attempt));
+            let retry = attempt < self.attempts && self.is_retryable(&failure) &&
+                        delay <= self.max_delay;
+            errors.push(failure.error);
+            if !retry {
+                break;
+            }
+            thread::sleep(delay);
+        }
+
+        if errors.len() == 1 {
+            return Err(errors.pop().unwrap());
+        }
+        let kind = errors.last().map(|e| e.kind()).unwrap_or(ErrorKind::Other);
+        Err(IoError::new(kind, RetryError {
+            url: url.to_string(),
+            attempts: errors.iter().map(|e| e.to_string()).collect(),
+        }))
+    }
+}
@@ -1,11 +1,16 @@
 use resources_package_package::Package;
-use super::AssetStore;
+use super::{AssetStore, AssetMetadata, ReadSeek, slice_range};
+use asset_path::{AssetPath, InvalidAssetPath};
+use metadata::guess_content_type;
 
+use std::io::Cursor;
 use std::path::Path;
 
 #[derive(Debug)]
 pub enum StaticStoreError {
-    NotFound(String)
+    NotFound(String),
+    /// The path leaves the store.
+    InvalidPath(InvalidAssetPath),
 }
 
 pub struct StaticStore {
@@ -17,8 +22,13 @@ impl StaticStore {
         StaticStore{ mem: m }
     }
 
-    fn find(&self, path: &str) -> Option<&[u8]> {
-        self.mem.find(&Path::new(path))
+    fn find(&self, path: &str) -> Result<&'static [u8], StaticStoreError> {
+        let path = try!(AssetPath::new(path).map_err(StaticStoreError::InvalidPath));
+        let mem: &'static Package = self.mem;
+        match mem.find(&Path::new(path.as_str())) {
+            Some(x) => Ok(x),
+            None => Err(StaticStoreError::NotFound(path.to_string()))
+        }
     }
 }
 
@@ -26,7 +36,29 @@ impl AssetStore<StaticStoreError> for StaticStore {
     fn load(&self, _: &str) { }
 
     fn is_loaded(&self, path: &str) -> Result<bool, StaticStoreError> {
-        Ok(self.find(path).is_some())
+        match self.find(path) {
+            Ok(_) => Ok(true),
+            Err(StaticStoreError::NotFound(_)) => Ok(false),
+            Err(e) => Err(e)
+        }
+    }
+
+    fn metadata(&self, path: &str) -> Result<AssetMetadata, StaticStoreError> {
+        let x = try!(self.find(path));
+        let mut meta = AssetMetadata::new();
+        meta.size = Some(x.len() as u64);
+        meta.content_type = guess_content_type(path);
+        Ok(meta)
+    }
+
+    fn open(&self, path: &str) -> Result<Box<ReadSeek>, StaticStoreError> {
+        let x = try!(self.find(path));
+        Ok(Box::new(Cursor::new(x)))
+    }
+
+    fn read_range(&self, path: &str, offset: u64, len: u64) -> Result<Vec<u8>, StaticStoreError> {
+        let x = try!(self.find(path));
+        Ok(slice_range(x, offset, len).to_vec())
     }
 
     fn unload(&self, _: &str) { }
@@ -36,10 +68,8 @@ impl AssetStore<StaticStoreError> for StaticStore {
     fn map_resource<O, F>(&self , path: &str, mapfn: F) -> Result<Option<O>, StaticStoreError>
         where F : Fn(&[u8]) -> O {
 
-        match self.find(path) {
-            Some(x) => Ok(Some(mapfn(x))),
-            None => Err(StaticStoreError::NotFound(path.to_string()))
-        }
+        let x = try!(self.find(path));
+        Ok(Some(mapfn(x)))
     }
 
     fn map_resource_block<O, F>(&self, path: &str, mapfn: F) -> Result<O, StaticStoreError>
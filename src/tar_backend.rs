@@ -0,0 +1,307 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Cursor, ErrorKind, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use std::io::Error as IoError;
+use std::io::Result as IoResult;
+
+use flate2::read::GzDecoder;
+
+use asset_path::AssetPath;
+use iostore::{IoStore, IoBackend, ListBackend, Delivery, check_sandboxed};
+use metadata::{AssetMetadata, guess_content_type};
+use super::ReadSeek;
+
+const BLOCK: u64 = 512;
+
+#[derive(Clone, Debug)]
+struct TarEntry {
+    /// Where the member's data starts in the uncompressed stream.
+    offset: u64,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+/// Serves resources out of a tar archive, optionally gzip compressed.
+/// The archive is scanned once when the store is made to find where
+/// each member starts.
+#[derive(Clone)]
+pub struct TarBackend {
+    path: PathBuf,
+    compressed: bool,
+    entries: Arc<HashMap<String, TarEntry>>,
+}
+
+/// Make a store from a `.tar` or `.tar.gz` archive.  Compression is
+/// detected from the contents rather than the file name.
+///
+/// Members of a compressed archive can't be seeked to directly, so
+/// each load decompresses the archive from the start up to the end of
+/// the member.  Only the member is kept, but loading many members of a
+/// large `.tar.gz` is slow; use a plain `.tar` or a pack for that.
+pub fn from_tar(path: &str) -> IoResult<IoStore<TarBackend>> {
+    let path = PathBuf::from(String::from(path));
+
+    let mut magic = [0; 2];
+    let compressed = {
+        let mut file = try!(File::open(&path));
+        try!(file.read(&mut magic)) == 2 && magic == [0x1f, 0x8b]
+    };
+
+    let file = try!(File::open(&path));
+    let entries = if compressed {
+        try!(scan(try!(GzDecoder::new(file))))
+    } else {
+        try!(scan(file))
+    };
+
+    Ok(IoStore::new(TarBackend {
+        path: path,
+        compressed: compressed,
+        entries: Arc::new(entries),
+    }))
+}
+
+fn invalid(why: &str) -> IoError {
+    IoError::new(ErrorKind::InvalidData, format!("Invalid tar archive: {}", why))
+}
+
+/// Reads a NUL terminated string field out of a header.
+fn field(header: &[u8]) -> String {
+    let end = header.iter().position(|&b| b == 0).unwrap_or(header.len());
+    String::from_utf8_lossy(&header[..end]).into_owned()
+}
+
+/// Reads a numeric header field, either octal text or GNU base-256.
+fn number(header: &[u8]) -> IoResult<u64> {
+    if header.len() > 0 && header[0] & 0x80 != 0 {
+        let mut n = (header[0] & 0x7f) as u64;
+        for &b in header[1..].iter() {
+            n = (n << 8) | b as u64;
+        }
+        return Ok(n);
+    }
+
+    let text = field(header);
+    let text = text.trim_matches(|c: char| c == ' ' || c == '\0');
+    if text.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(text, 8).map_err(|_| invalid("bad number in header"))
+}
+
+/// Finds the `path` record in a pax extended header.
+fn pax_path(data: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(data);
+    text.lines()
+        .filter_map(|record| record.splitn(2, ' ').nth(1))
+        .filter_map(|record| {
+            let mut kv = record.splitn(2, '=');
+            match (kv.next(), kv.next()) {
+                (Some("path"), Some(value)) => Some(value.to_string()),
+                _ => None
+            }
+        })
+        .last()
+}
+
+fn skip<R: Read>(r: &mut R, n: u64) -> IoResult<()> {
+    let skipped = try!(io::copy(&mut r.by_ref().take(n), &mut io::sink()));
+    if skipped < n {
+        Err(invalid("unexpected end of archive"))
+    } else {
+        Ok(())
+    }
+}
+
+fn scan<R: Read>(mut r: R) -> IoResult<HashMap<String, TarEntry>> {
+    let mut entries = HashMap::new();
+    let mut pos = 0;
+    let mut long_name = None;
+    let mut header = [0; 512];
+
+    loop {
+        let mut filled = 0;
+        while filled < header.len() {
+            match try!(r.read(&mut header[filled..])) {
+                0 => break,
+                n => filled += n
+            }
+        }
+        // A zero block, or a truncated archive without one, ends it.
+        if filled < header.len() || header.iter().all(|&b| b == 0) {
+            break;
+        }
+        pos += BLOCK;
+
+        let size = try!(number(&header[124..136]));
+        let padded = (size + BLOCK - 1) / BLOCK * BLOCK;
+        let kind = header[156];
+
+        match kind {
+            b'L' | b'x' => {
+                let mut data = vec![];
+                try!(r.by_ref().take(size).read_to_end(&mut data));
+                try!(skip(&mut r, padded - data.len() as u64));
+                long_name = if kind == b'L' { Some(field(&data)) } else { pax_path(&data) };
+            }
+            b'0' | b'\0' | b'7' => {
+                let name = match long_name.take() {
+                    Some(name) => name,
+                    None => {
+                        let prefix = field(&header[345..500]);
+                        let name = field(&header[0..100]);
+                        if prefix.is_empty() { name } else { format!("{}/{}", prefix, name) }
+                    }
+                };
+                let modified = try!(number(&header[136..148]));
+
+                // Members that would escape the archive are never served.
+                if check_sandboxed(&name).is_ok() {
+                    entries.insert(AssetPath::from(name).to_string(), TarEntry {
+                        offset: pos,
+                        size: size,
+                        modified: Some(UNIX_EPOCH + Duration::from_secs(modified)),
+                    });
+                }
+                try!(skip(&mut r, padded));
+            }
+            _ => {
+                long_name = None;
+                try!(skip(&mut r, padded));
+            }
+        }
+        pos += padded;
+    }
+    Ok(entries)
+}
+
+/// A window onto one member of an uncompressed archive.
+struct TarMember {
+    file: File,
+    start: u64,
+    len: u64,
+    pos: u64,
+}
+
+impl Read for TarMember {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let left = self.len.saturating_sub(self.pos);
+        let want = cmp::min(left, buf.len() as u64) as usize;
+        if want == 0 {
+            return Ok(0);
+        }
+        try!(self.file.seek(SeekFrom::Start(self.start + self.pos)));
+        let n = try!(self.file.read(&mut buf[..want]));
+        if n == 0 {
+            return Err(invalid("unexpected end of archive"));
+        }
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for TarMember {
+    fn seek(&mut self, pos: SeekFrom) -> IoResult<u64> {
+        let target = match pos {
+            SeekFrom::Start(n) => n as i64,
+            SeekFrom::Current(n) => self.pos as i64 + n,
+            SeekFrom::End(n) => self.len as i64 + n,
+        };
+        if target < 0 {
+            return Err(IoError::new(ErrorKind::InvalidInput, "Seek to a negative position"));
+        }
+        self.pos = target as u64;
+        Ok(self.pos)
+    }
+}
+
+impl TarBackend {
+    fn entry(&self, file: &AssetPath) -> IoResult<&TarEntry> {
+        try!(check_sandboxed(file.as_str()));
+        match self.entries.get(file.as_str()) {
+            Some(entry) => Ok(entry),
+            None => Err(IoError::new(
+                ErrorKind::NotFound,
+                format!("Given path does not exist: {} does not contain {}",
+                        self.path.display(), file)
+            ))
+        }
+    }
+
+    fn read_member(&self, file: &AssetPath, offset: u64, len: u64) -> IoResult<Vec<u8>> {
+        let entry = try!(self.entry(file));
+        let offset = cmp::min(offset, entry.size);
+        let len = cmp::min(len, entry.size - offset);
+
+        let archive = try!(File::open(&self.path));
+        let mut data = vec![];
+        if self.compressed {
+            let mut archive = try!(GzDecoder::new(archive));
+            try!(skip(&mut archive, entry.offset + offset));
+            try!(archive.take(len).read_to_end(&mut data));
+        } else {
+            let mut archive = archive;
+            try!(archive.seek(SeekFrom::Start(entry.offset + offset)));
+            try!(archive.take(len).read_to_end(&mut data));
+        }
+        if (data.len() as u64) < len {
+            return Err(invalid("unexpected end of archive"));
+        }
+        Ok(data)
+    }
+
+    fn extract(&self, file: &AssetPath) -> IoResult<Vec<u8>> {
+        self.read_member(file, 0, ::std::u64::MAX)
+    }
+}
+
+impl IoBackend for TarBackend {
+    fn go_get(&self, file: &AssetPath, delivery: Delivery) {
+        let backend = self.clone();
+        let file = file.clone();
+        thread::spawn(move || {
+            delivery.deliver(backend.extract(&file));
+        });
+    }
+
+    fn metadata(&self, file: &AssetPath) -> IoResult<AssetMetadata> {
+        let entry = try!(self.entry(file));
+        let mut meta = AssetMetadata::new();
+        meta.size = Some(entry.size);
+        meta.modified = entry.modified;
+        meta.content_type = guess_content_type(file.as_str());
+        Ok(meta)
+    }
+
+    fn open(&self, file: &AssetPath) -> IoResult<Box<ReadSeek>> {
+        if self.compressed {
+            return Ok(Box::new(Cursor::new(try!(self.extract(file)))));
+        }
+
+        let entry = try!(self.entry(file));
+        Ok(Box::new(TarMember {
+            file: try!(File::open(&self.path)),
+            start: entry.offset,
+            len: entry.size,
+            pos: 0,
+        }))
+    }
+
+    fn read_range(&self, file: &AssetPath, offset: u64, len: u64) -> IoResult<Vec<u8>> {
+        self.read_member(file, offset, len)
+    }
+}
+
+impl ListBackend for TarBackend {
+    fn list(&self) -> IoResult<Vec<String>> {
+        let mut all: Vec<String> = self.entries.keys().cloned().collect();
+        all.sort();
+        Ok(all)
+    }
+}
@@ -0,0 +1,214 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::io::Read;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use std::io::Result as IoResult;
+
+use time;
+use timeouts::DeadlineReader;
+
+/// How hard a url store may use the network.  Requests over the
+/// per-host limit wait for an earlier one to finish, and every body is
+/// read no faster than the bandwidth limit allows, which is shared by
+/// all the store's loading threads.  Time spent waiting on either
+/// limit doesn't count against the transfer timeout.
+///
+/// Readers returned by `open` only obey the bandwidth limit.  They
+/// can sit idle for as long as their owner likes, and holding a slot
+/// all that time could leave every other request to the host waiting.
+///
+/// ```ignore
+/// let mut throttle = Throttle::new();
+/// throttle.set_per_host(4);
+/// throttle.set_bytes_per_second(512 * 1024);
+/// let store = from_url("http://example.com/assets/").unwrap().throttle(throttle);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Throttle {
+    per_host: Option<usize>,
+    bytes_per_second: Option<u64>,
+    burst: Option<u64>,
+}
+
+impl Throttle {
+    /// No limits at all.
+    pub fn new() -> Throttle {
+        Throttle { per_host: None, bytes_per_second: None, burst: None }
+    }
+
+    /// The most requests in flight to one host at a time.
+    pub fn set_per_host(&mut self, requests: usize) {
+        self.per_host = Some(if requests == 0 { 1 } else { requests });
+    }
+
+    /// The most body bytes read per second, across every request.
+    pub fn set_bytes_per_second(&mut self, bytes: u64) {
+        self.bytes_per_second = Some(if bytes == 0 { 1 } else { bytes });
+    }
+
+    /// How many bytes may be read at full speed after a quiet spell.
+    /// Defaults to one second's worth.
+    pub fn set_burst(&mut self, bytes: u64) {
+        self.burst = Some(if bytes == 0 { 1 } else { bytes });
+    }
+}
+
+/// A token bucket: `tokens` refill at `rate` per second up to
+/// `capacity`, and reading takes one per byte.
+struct Bucket {
+    rate: u64,
+    capacity: u64,
+    tokens: f64,
+    /// When the bucket was last refilled, in `time::precise_time_ns`.
+    refilled: u64,
+}
+
+/// The state a `Throttle` is enforced with, shared by every clone of
+/// a backend.
+pub struct Limiter {
+    per_host: Option<usize>,
+    active: Mutex<HashMap<String, usize>>,
+    finished: Condvar,
+    bucket: Option<Mutex<Bucket>>,
+}
+
+impl Limiter {
+    pub fn new(throttle: &Throttle) -> Arc<Limiter> {
+        let bucket = throttle.bytes_per_second.map(|rate| {
+            let capacity = throttle.burst.unwrap_or(rate);
+            Mutex::new(Bucket {
+                rate: rate,
+                capacity: capacity,
+                tokens: capacity as f64,
+                refilled: time::precise_time_ns(),
+            })
+        });
+        Arc::new(Limiter {
+            per_host: throttle.per_host,
+            active: Mutex::new(HashMap::new()),
+            finished: Condvar::new(),
+            bucket: bucket,
+        })
+    }
+
+    /// Wait for a free slot on the host of `url`.  The slot is held
+    /// until the permit is dropped.
+    pub fn acquire(limiter: &Arc<Limiter>, url: &str) -> Permit {
+        let max = match limiter.per_host {
+            Some(max) => max,
+            None => { return Permit { limiter: limiter.clone(), host: None }; }
+        };
+        let host = host_of(url).to_string();
+        if let Ok(mut active) = limiter.active.lock() {
+            while active.get(&host).map_or(false, |&n| n >= max) {
+                active = match limiter.finished.wait(active) {
+                    Ok(active) => active,
+                    Err(_) => { return Permit { limiter: limiter.clone(), host: None }; }
+                };
+            }
+            *active.entry(host.clone()).or_insert(0) += 1;
+        }
+        Permit { limiter: limiter.clone(), host: Some(host) }
+    }
+
+    /// A permit that obeys the bandwidth limit but takes no slot on
+    /// any host.
+    pub fn bandwidth_only(limiter: &Arc<Limiter>) -> Permit {
+        Permit { limiter: limiter.clone(), host: None }
+    }
+
+    /// The most bytes worth reading at once, so one reader can't
+    /// drain the bucket far ahead of the others.
+    fn chunk(&self, wanted: usize) -> usize {
+        match self.bucket.as_ref().and_then(|bucket| bucket.lock().ok()) {
+            Some(bucket) => cmp::min(wanted as u64, bucket.capacity) as usize,
+            None => wanted
+        }
+    }
+
+    /// Take `bytes` tokens, sleeping off any debt this leaves.  Returns
+    /// how long it slept, in nanoseconds.
+    fn spend(&self, bytes: usize) -> u64 {
+        let wait = match self.bucket.as_ref().and_then(|bucket| bucket.lock().ok()) {
+            Some(mut bucket) => {
+                let now = time::precise_time_ns();
+                let elapsed = now.saturating_sub(bucket.refilled) as f64 / 1e9;
+                bucket.tokens = (bucket.tokens + elapsed * bucket.rate as f64)
+                    .min(bucket.capacity as f64) - bytes as f64;
+                bucket.refilled = now;
+                if bucket.tokens < 0.0 { -bucket.tokens / bucket.rate as f64 } else { 0.0 }
+            }
+            None => 0.0
+        };
+        if wait <= 0.0 {
+            return 0;
+        }
+        let nanos = (wait * 1e9).ceil() as u64;
+        thread::sleep(Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32));
+        nanos
+    }
+}
+
+/// A slot for one request to a host.
+pub struct Permit {
+    limiter: Arc<Limiter>,
+    host: Option<String>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let host = match self.host.take() {
+            Some(host) => host,
+            None => { return; }
+        };
+        if let Ok(mut active) = self.limiter.active.lock() {
+            let left = match active.get_mut(&host) {
+                Some(n) => { *n -= 1; *n }
+                None => 0
+            };
+            if left == 0 {
+                active.remove(&host);
+            }
+        }
+        self.limiter.finished.notify_all();
+    }
+}
+
+/// Reads a response body within the bandwidth limit, holding its
+/// request's permit until the body is dropped.  The body's deadline is
+/// moved back by however long the limit makes it wait.
+pub struct Throttled<R> {
+    inner: DeadlineReader<R>,
+    permit: Permit,
+}
+
+impl <R: Read> Throttled<R> {
+    pub fn new(inner: DeadlineReader<R>, permit: Permit) -> Throttled<R> {
+        Throttled { inner: inner, permit: permit }
+    }
+}
+
+impl <R: Read> Read for Throttled<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let len = self.permit.limiter.chunk(buf.len());
+        let n = try!(self.inner.read(&mut buf[..len]));
+        let slept = self.permit.limiter.spend(n);
+        self.inner.postpone(slept);
+        Ok(n)
+    }
+}
+
+/// The `host:port` part of an absolute url.
+fn host_of(url: &str) -> &str {
+    let rest = match url.find("://") {
+        Some(i) => &url[i + 3..],
+        None => url
+    };
+    match rest.find(|c: char| c == '/' || c == '?' || c == '#') {
+        Some(end) => &rest[..end],
+        None => rest
+    }
+}
@@ -0,0 +1,224 @@
+use std::cell::Cell;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr};
+use std::time::Duration;
+
+use std::io::Error as IoError;
+use std::io::Result as IoResult;
+
+use hyper;
+use hyper::net::{NetworkConnector, NetworkStream};
+use time;
+
+/// How long a url store waits on a server before giving up.  Every
+/// timeout fails the load with `ErrorKind::TimedOut`.
+///
+/// ```ignore
+/// let mut timeouts = Timeouts::new();
+/// timeouts.set_connect(Duration::from_secs(5));
+/// timeouts.set_first_byte(Duration::from_secs(10));
+/// timeouts.set_transfer(Duration::from_secs(60));
+/// let store = from_url("http://example.com/assets/").unwrap().timeouts(timeouts);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Timeouts {
+    connect: Option<Duration>,
+    first_byte: Option<Duration>,
+    transfer: Option<Duration>,
+}
+
+impl Timeouts {
+    /// No timeouts at all.
+    pub fn new() -> Timeouts {
+        Timeouts { connect: None, first_byte: None, transfer: None }
+    }
+
+    /// The time allowed to open a connection.  It is added to the wait
+    /// for the response headers; opening the connection itself can't be
+    /// interrupted, and is only bounded by the system's connect timeout.
+    pub fn set_connect(&mut self, timeout: Duration) {
+        self.connect = Some(timeout);
+    }
+
+    /// The time allowed between sending a request and the start of the
+    /// response.  It also bounds any pause while the body is read, so
+    /// a server that stalls halfway is noticed too.
+    pub fn set_first_byte(&mut self, timeout: Duration) {
+        self.first_byte = Some(timeout);
+    }
+
+    /// The time allowed for a whole download, from connecting to the
+    /// last byte of the body.
+    pub fn set_transfer(&mut self, timeout: Duration) {
+        self.transfer = Some(timeout);
+    }
+
+    pub fn first_byte(&self) -> Option<Duration> {
+        self.first_byte
+    }
+
+    /// How long to wait for the response headers of a request that is
+    /// started now.
+    pub fn header_wait(&self) -> Option<Duration> {
+        let wait = match (self.connect, self.first_byte) {
+            (Some(connect), Some(first_byte)) => Some(connect + first_byte),
+            (Some(connect), None) => Some(connect),
+            (None, first_byte) => first_byte,
+        };
+        match (wait, self.transfer) {
+            (Some(wait), Some(transfer)) => Some(if wait < transfer { wait } else { transfer }),
+            (wait, transfer) => wait.or(transfer)
+        }
+    }
+
+    /// The moment, in `time::precise_time_ns`, by which a transfer that
+    /// starts now has to be finished.
+    pub fn deadline(&self) -> Option<u64> {
+        self.transfer.map(|transfer| time::precise_time_ns() + nanos(transfer))
+    }
+}
+
+pub fn nanos(d: Duration) -> u64 {
+    d.as_secs() * 1_000_000_000 + d.subsec_nanos() as u64
+}
+
+thread_local!(static READ_DEADLINE: Cell<Option<u64>> = Cell::new(None));
+
+/// Run `f` with every read from a `TimedStream` on this thread given
+/// up at `deadline`, in `time::precise_time_ns`.
+pub fn with_deadline<T, F: FnOnce() -> T>(deadline: Option<u64>, f: F) -> T {
+    let previous = READ_DEADLINE.with(|current| {
+        let previous = current.get();
+        current.set(deadline);
+        previous
+    });
+    let out = f();
+    READ_DEADLINE.with(|current| current.set(previous));
+    out
+}
+
+/// Opens connections with `inner` and wraps them in `TimedStream`s.
+pub struct TimedConnector<C>(pub C);
+
+impl <C: NetworkConnector<Stream=S>, S: NetworkStream + Send> NetworkConnector for TimedConnector<C> {
+    type Stream = TimedStream<S>;
+
+    fn connect(&self, host: &str, port: u16, scheme: &str) -> hyper::Result<TimedStream<S>> {
+        let inner = try!(self.0.connect(host, port, scheme));
+        Ok(TimedStream { inner: inner, read_timeout: Cell::new(None) })
+    }
+}
+
+/// A connection whose reads never wait past the deadline set with
+/// `with_deadline`.  The socket's read timeout is cut down to the time
+/// that is left before each read, so a server that stalls in the
+/// middle of a read can't hold on to the reading thread.
+pub struct TimedStream<S> {
+    inner: S,
+    /// The read timeout hyper asked for, which applies when there is
+    /// no deadline or it is further away.
+    read_timeout: Cell<Option<Duration>>,
+}
+
+impl <S: NetworkStream> Read for TimedStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let deadline = match READ_DEADLINE.with(|current| current.get()) {
+            Some(deadline) => deadline,
+            None => { return self.inner.read(buf); }
+        };
+        let now = time::precise_time_ns();
+        if now >= deadline {
+            return Err(IoError::new(ErrorKind::TimedOut, "Read deadline has passed"));
+        }
+        let left = deadline - now;
+        let left = Duration::new(left / 1_000_000_000, (left % 1_000_000_000) as u32);
+        let wait = match self.read_timeout.get() {
+            Some(timeout) if timeout < left => timeout,
+            _ => left
+        };
+        try!(self.inner.set_read_timeout(Some(wait)));
+        let out = self.inner.read(buf);
+        let _ = self.inner.set_read_timeout(self.read_timeout.get());
+        out
+    }
+}
+
+impl <S: NetworkStream> Write for TimedStream<S> {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        self.inner.flush()
+    }
+}
+
+impl <S: NetworkStream> NetworkStream for TimedStream<S> {
+    fn peer_addr(&mut self) -> IoResult<SocketAddr> {
+        self.inner.peer_addr()
+    }
+
+    fn set_read_timeout(&self, dur: Option<Duration>) -> IoResult<()> {
+        self.read_timeout.set(dur);
+        self.inner.set_read_timeout(dur)
+    }
+
+    fn set_write_timeout(&self, dur: Option<Duration>) -> IoResult<()> {
+        self.inner.set_write_timeout(dur)
+    }
+
+    fn close(&mut self, how: Shutdown) -> IoResult<()> {
+        self.inner.close(how)
+    }
+
+    fn set_previous_response_expected_no_content(&mut self, expected: bool) {
+        self.inner.set_previous_response_expected_no_content(expected)
+    }
+
+    fn previous_response_expected_no_content(&self) -> bool {
+        self.inner.previous_response_expected_no_content()
+    }
+}
+
+pub fn timed_out(url: &str) -> IoError {
+    IoError::new(ErrorKind::TimedOut, format!("Timed out fetching {}", url))
+}
+
+/// Fails reads once the deadline has passed, and reports sockets
+/// that time out as `ErrorKind::TimedOut` whatever the platform calls
+/// them.  Reads from a `TimedStream` underneath give up at the deadline
+/// too, rather than only being refused once it has passed.
+pub struct DeadlineReader<R> {
+    inner: R,
+    deadline: Option<u64>,
+    url: String,
+}
+
+impl <R: Read> DeadlineReader<R> {
+    pub fn new(inner: R, deadline: Option<u64>, url: &str) -> DeadlineReader<R> {
+        DeadlineReader { inner: inner, deadline: deadline, url: url.to_string() }
+    }
+
+    /// Move the deadline back by `nanos`, for time spent waiting on
+    /// something other than the server.
+    pub fn postpone(&mut self, nanos: u64) {
+        if let Some(ref mut deadline) = self.deadline {
+            *deadline = deadline.saturating_add(nanos);
+        }
+    }
+}
+
+impl <R: Read> Read for DeadlineReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        if self.deadline.map_or(false, |deadline| time::precise_time_ns() > deadline) {
+            return Err(timed_out(&self.url));
+        }
+        let deadline = self.deadline;
+        let inner = &mut self.inner;
+        match with_deadline(deadline, || inner.read(buf)) {
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut =>
+                Err(timed_out(&self.url)),
+            other => other
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::RwLock;
+
+use super::{AssetStore, AssetMetadata, ReadSeek};
+use asset_path::AssetPath;
+
+/// Wraps another store and serves the best available variant of each
+/// resource.  A variant tag is inserted before the extension, so with
+/// the tags `@2x` and `.de` a load of `ui/button.png` tries
+/// `ui/button@2x.png`, then `ui/button.de.png`, then `ui/button.png`.
+/// Names with several extensions get the tag before the first, so
+/// `maps/town.tar.gz` tries `maps/town@2x.tar.gz`.
+///
+/// Candidates are probed with `metadata` and the outcome is
+/// remembered, including when no candidate exists, so each path is
+/// only probed once until the tags change or `forget_variants` is
+/// called.  Once a resource has been read it stays bound to that
+/// variant until it is unloaded.  A variant that fails to load is not
+/// remembered, so it is probed again after `forget_variants`.
+///
+/// ```ignore
+/// let store = VariantStore::new(from_directory("./assets/"), &["@2x", ".de"]);
+/// assert_eq!(store.resolve("ui/button.png").as_str(), "ui/button@2x.png");
+/// ```
+pub struct VariantStore<S, E> {
+    store: S,
+    tags: RwLock<Vec<String>>,
+    /// The outcome of probing each requested path.
+    resolved: RwLock<HashMap<AssetPath, AssetPath>>,
+    /// The variant each path was successfully read as.
+    chosen: RwLock<HashMap<AssetPath, AssetPath>>,
+    _e: PhantomData<*const E>
+}
+
+impl <S: AssetStore<E>, E> VariantStore<S, E> {
+    /// `tags` are tried in order, the most preferred first.
+    pub fn new(store: S, tags: &[&str]) -> VariantStore<S, E> {
+        VariantStore {
+            store: store,
+            tags: RwLock::new(tags.iter().map(|t| t.to_string()).collect()),
+            resolved: RwLock::new(HashMap::new()),
+            chosen: RwLock::new(HashMap::new()),
+            _e: PhantomData
+        }
+    }
+
+    /// Change the active tags, such as when the locale changes.
+    /// Resources that are already loaded stay loaded under the path
+    /// they were resolved to, and unloading them unloads that path.
+    pub fn set_variants(&self, tags: &[&str]) {
+        if let Ok(mut current) = self.tags.write() {
+            *current = tags.iter().map(|t| t.to_string()).collect();
+        }
+        self.forget_variants();
+    }
+
+    /// Forget every probe made so far, so new files are noticed.
+    pub fn forget_variants(&self) {
+        if let Ok(mut resolved) = self.resolved.write() {
+            resolved.clear();
+        }
+    }
+
+    /// `path` with `tag` inserted before its first extension.
+    fn variant(path: &AssetPath, tag: &str) -> AssetPath {
+        let name = match path.file_name() {
+            Some(name) => name,
+            None => { return path.clone(); }
+        };
+        // A leading dot starts a hidden name rather than an extension.
+        let name = match name.char_indices().skip(1).find(|&(_, c)| c == '.') {
+            Some((i, _)) => format!("{}{}{}", &name[..i], tag, &name[i..]),
+            None => format!("{}{}", name, tag)
+        };
+        match path.parent() {
+            Some(parent) => parent.join(name),
+            None => AssetPath::from(name)
+        }
+    }
+
+    /// The concrete path that is served for `path`.  When no variant
+    /// exists this is `path` itself.
+    pub fn resolve<P: Into<AssetPath>>(&self, path: P) -> AssetPath {
+        let path = path.into();
+        if let Ok(resolved) = self.resolved.read() {
+            if let Some(found) = resolved.get(&path) {
+                return found.clone();
+            }
+        }
+
+        let tags = match self.tags.read() {
+            Ok(tags) => tags.clone(),
+            Err(_) => vec![]
+        };
+        let found = tags.iter()
+            .map(|tag| VariantStore::<S, E>::variant(&path, tag))
+            .find(|candidate| self.store.metadata(candidate.as_str()).is_ok())
+            .unwrap_or_else(|| path.clone());
+        if let Ok(mut resolved) = self.resolved.write() {
+            resolved.insert(path, found.clone());
+        }
+        found
+    }
+
+    /// The variant `path` was loaded as, or the one it resolves to now
+    /// if it isn't loaded.
+    fn bound(&self, path: &str) -> AssetPath {
+        let path = AssetPath::from(path);
+        if let Ok(chosen) = self.chosen.read() {
+            if let Some(found) = chosen.get(&path) {
+                return found.clone();
+            }
+        }
+        self.resolve(path)
+    }
+
+    /// Remember that `path` was read as `variant`.
+    fn bind(&self, path: &str, variant: &AssetPath) {
+        let path = AssetPath::from(path);
+        if let Ok(chosen) = self.chosen.read() {
+            if chosen.contains_key(&path) {
+                return;
+            }
+        }
+        if let Ok(mut chosen) = self.chosen.write() {
+            chosen.entry(path).or_insert(variant.clone());
+        }
+    }
+}
+
+impl <S: AssetStore<E>, E> AssetStore<E> for VariantStore<S, E> {
+    fn load(&self, path: &str) {
+        self.store.load(self.bound(path).as_str());
+    }
+
+    fn is_loaded(&self, path: &str) -> Result<bool, E> {
+        let variant = self.bound(path);
+        let loaded = try!(self.store.is_loaded(variant.as_str()));
+        if loaded {
+            self.bind(path, &variant);
+        }
+        Ok(loaded)
+    }
+
+    fn metadata(&self, path: &str) -> Result<AssetMetadata, E> {
+        self.store.metadata(self.bound(path).as_str())
+    }
+
+    fn open(&self, path: &str) -> Result<Box<ReadSeek>, E> {
+        self.store.open(self.bound(path).as_str())
+    }
+
+    fn read_range(&self, path: &str, offset: u64, len: u64) -> Result<Vec<u8>, E> {
+        self.store.read_range(self.bound(path).as_str(), offset, len)
+    }
+
+    /// Unloads exactly the variant that was loaded, even if the tags
+    /// changed since.
+    fn unload(&self, path: &str) {
+        let loaded = match self.chosen.write() {
+            Ok(mut chosen) => chosen.remove(&AssetPath::from(path)),
+            Err(_) => None
+        };
+        match loaded {
+            Some(loaded) => self.store.unload(loaded.as_str()),
+            None => self.store.unload(self.resolve(path).as_str())
+        }
+    }
+
+    fn unload_everything(&self) {
+        if let Ok(mut chosen) = self.chosen.write() {
+            chosen.clear();
+        }
+        self.store.unload_everything();
+    }
+
+    fn map_resource<O, F>(&self , path: &str, mapfn: F) -> Result<Option<O>, E>
+        where F: Fn(&[u8]) -> O {
+
+        let variant = self.bound(path);
+        let out = try!(self.store.map_resource(variant.as_str(), mapfn));
+        if out.is_some() {
+            self.bind(path, &variant);
+        }
+        Ok(out)
+    }
+
+    fn map_resource_block<O, F>(&self, path: &str, mapfn: F) -> Result<O, E>
+        where F: Fn(&[u8]) -> O {
+
+        let variant = self.bound(path);
+        let out = try!(self.store.map_resource_block(variant.as_str(), mapfn));
+        self.bind(path, &variant);
+        Ok(out)
+    }
+}
@@ -0,0 +1,315 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Cursor, ErrorKind, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use std::io::Error as IoError;
+use std::io::Result as IoResult;
+
+use flate2::Crc;
+use flate2::read::DeflateDecoder;
+
+use asset_path::AssetPath;
+use iostore::{IoStore, IoBackend, ListBackend, Delivery, check_sandboxed};
+use limits::SizeLimits;
+use metadata::{AssetMetadata, guess_content_type};
+use super::ReadSeek;
+
+const LOCAL_SIG: u32 = 0x04034b50;
+const CENTRAL_SIG: u32 = 0x02014b50;
+const END_SIG: u32 = 0x06054b50;
+
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+
+#[derive(Clone, Debug)]
+pub enum ZipError {
+    /// The archive is malformed.
+    InvalidArchive(&'static str),
+    /// The archive uses a feature that is not supported, such as
+    /// zip64 or encryption.
+    Unsupported(&'static str),
+    /// A member is compressed with a method other than stored or
+    /// deflated.
+    UnsupportedCompression(u16),
+    /// A member did not match the checksum recorded in the archive.
+    BadCrc {
+        path: String,
+        expected: u32,
+        actual: u32,
+    },
+}
+
+impl fmt::Display for ZipError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ZipError::InvalidArchive(why) => write!(f, "Invalid zip archive: {}", why),
+            ZipError::Unsupported(what) => write!(f, "Unsupported zip feature: {}", what),
+            ZipError::UnsupportedCompression(method) =>
+                write!(f, "Unsupported zip compression method: {}", method),
+            ZipError::BadCrc { ref path, expected, actual } =>
+                write!(f, "Bad crc for {}: expected {:08x}, found {:08x}", path, expected, actual),
+        }
+    }
+}
+
+impl Error for ZipError {
+    fn description(&self) -> &str {
+        match *self {
+            ZipError::InvalidArchive(_) => "invalid zip archive",
+            ZipError::Unsupported(_) => "unsupported zip feature",
+            ZipError::UnsupportedCompression(_) => "unsupported zip compression method",
+            ZipError::BadCrc { .. } => "bad crc in zip archive",
+        }
+    }
+}
+
+fn zip_error(err: ZipError) -> IoError {
+    IoError::new(ErrorKind::InvalidData, err)
+}
+
+#[derive(Clone, Debug)]
+struct ZipEntry {
+    flags: u16,
+    method: u16,
+    crc: u32,
+    compressed_size: u64,
+    size: u64,
+    header_offset: u64,
+    modified: Option<SystemTime>,
+}
+
+/// Serves resources out of a zip archive.  The central directory is
+/// read once when the store is made, members are inflated on demand.
+#[derive(Clone)]
+pub struct ZipBackend {
+    path: PathBuf,
+    entries: Arc<HashMap<String, ZipEntry>>,
+    limits: SizeLimits,
+}
+
+pub fn from_zip(path: &str) -> IoResult<IoStore<ZipBackend>> {
+    from_zip_with_limits(path, SizeLimits::new())
+}
+
+/// Make a store from a zip archive that refuses to inflate members
+/// bigger than the given limits.  The sizes recorded in the archive
+/// are checked first, and inflation stops as soon as a member grows
+/// past its recorded size, so a hostile archive can't exhaust memory.
+pub fn from_zip_with_limits(path: &str, limits: SizeLimits) -> IoResult<IoStore<ZipBackend>> {
+    let path = PathBuf::from(String::from(path));
+    let mut file = try!(File::open(&path));
+    let entries = try!(read_central_directory(&mut file));
+    Ok(IoStore::new(ZipBackend { path: path, entries: Arc::new(entries), limits: limits }))
+}
+
+fn le_u16(buf: &[u8], at: usize) -> u16 {
+    buf[at] as u16 | (buf[at + 1] as u16) << 8
+}
+
+fn le_u32(buf: &[u8], at: usize) -> u32 {
+    le_u16(buf, at) as u32 | (le_u16(buf, at + 2) as u32) << 16
+}
+
+/// Converts an MS-DOS date and time, as stored in zip archives.
+fn dos_time(date: u16, time: u16) -> Option<SystemTime> {
+    let year = 1980 + (date >> 9) as i64;
+    let month = ((date >> 5) & 0xf) as i64;
+    let day = (date & 0x1f) as i64;
+    if month < 1 || month > 12 || day < 1 {
+        return None;
+    }
+
+    // Days since the epoch of a date in the proleptic Gregorian calendar.
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y / 400;
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = (era * 146097 + doe - 719468) as u64;
+
+    let secs = (time >> 11) as u64 * 3600 + ((time >> 5) & 0x3f) as u64 * 60 + (time & 0x1f) as u64 * 2;
+    Some(UNIX_EPOCH + Duration::from_secs(days * 86400 + secs))
+}
+
+fn read_central_directory(file: &mut File) -> IoResult<HashMap<String, ZipEntry>> {
+    // The end record sits at the very end, followed only by a comment
+    // of at most 64k.
+    let file_len = try!(file.seek(SeekFrom::End(0)));
+    let tail_len = cmp::min(file_len, 22 + 0xffff);
+    try!(file.seek(SeekFrom::Start(file_len - tail_len)));
+    let mut tail = vec![];
+    try!(file.by_ref().take(tail_len).read_to_end(&mut tail));
+
+    let end = match (0..tail.len().saturating_sub(21)).rev().find(|&i| le_u32(&tail, i) == END_SIG) {
+        Some(end) => end,
+        None => { return Err(zip_error(ZipError::InvalidArchive("missing end of central directory"))); }
+    };
+    let count = le_u16(&tail, end + 10);
+    let dir_size = le_u32(&tail, end + 12) as u64;
+    let dir_offset = le_u32(&tail, end + 16) as u64;
+    if count == 0xffff || dir_offset == 0xffffffff {
+        return Err(zip_error(ZipError::Unsupported("zip64")));
+    }
+    if dir_offset + dir_size > file_len {
+        return Err(zip_error(ZipError::InvalidArchive("central directory out of bounds")));
+    }
+
+    try!(file.seek(SeekFrom::Start(dir_offset)));
+    let mut dir = vec![];
+    try!(file.by_ref().take(dir_size).read_to_end(&mut dir));
+
+    let mut entries = HashMap::new();
+    let mut at = 0;
+    for _ in 0..count {
+        if at + 46 > dir.len() || le_u32(&dir, at) != CENTRAL_SIG {
+            return Err(zip_error(ZipError::InvalidArchive("corrupt central directory")));
+        }
+        let name_end = at + 46 + le_u16(&dir, at + 28) as usize;
+        if name_end > dir.len() {
+            return Err(zip_error(ZipError::InvalidArchive("corrupt central directory")));
+        }
+        let name = String::from_utf8_lossy(&dir[at + 46..name_end]).into_owned();
+        let entry = ZipEntry {
+            flags: le_u16(&dir, at + 8),
+            method: le_u16(&dir, at + 10),
+            crc: le_u32(&dir, at + 16),
+            compressed_size: le_u32(&dir, at + 20) as u64,
+            size: le_u32(&dir, at + 24) as u64,
+            header_offset: le_u32(&dir, at + 42) as u64,
+            modified: dos_time(le_u16(&dir, at + 14), le_u16(&dir, at + 12)),
+        };
+        at = name_end + le_u16(&dir, at + 30) as usize + le_u16(&dir, at + 32) as usize;
+
+        // Directories have no contents of their own, and members that
+        // would escape the archive are never served.
+        if !name.ends_with('/') && check_sandboxed(&name).is_ok() {
+            entries.insert(AssetPath::from(name).to_string(), entry);
+        }
+    }
+    Ok(entries)
+}
+
+impl ZipBackend {
+    fn entry(&self, file: &AssetPath) -> IoResult<&ZipEntry> {
+        try!(check_sandboxed(file.as_str()));
+        match self.entries.get(file.as_str()) {
+            Some(entry) => Ok(entry),
+            None => Err(IoError::new(
+                ErrorKind::NotFound,
+                format!("Given path does not exist: {} does not contain {}",
+                        self.path.display(), file)
+            ))
+        }
+    }
+
+    /// Opens the archive positioned at the start of a member's data.
+    fn member(&self, entry: &ZipEntry) -> IoResult<File> {
+        if entry.flags & 1 != 0 {
+            return Err(zip_error(ZipError::Unsupported("encryption")));
+        }
+
+        let mut archive = try!(File::open(&self.path));
+        try!(archive.seek(SeekFrom::Start(entry.header_offset)));
+        let mut header = [0; 30];
+        try!(archive.read_exact(&mut header));
+        if le_u32(&header, 0) != LOCAL_SIG {
+            return Err(zip_error(ZipError::InvalidArchive("corrupt local header")));
+        }
+        let skip = le_u16(&header, 26) as i64 + le_u16(&header, 28) as i64;
+        try!(archive.seek(SeekFrom::Current(skip)));
+        Ok(archive)
+    }
+
+    /// The member's contents, never more than the size recorded for it.
+    fn inflate(&self, entry: &ZipEntry) -> IoResult<Box<Read>> {
+        let raw = try!(self.member(entry)).take(entry.compressed_size);
+        match entry.method {
+            STORED => Ok(Box::new(raw.take(entry.size))),
+            DEFLATED => Ok(Box::new(DeflateDecoder::new(raw).take(entry.size))),
+            method => Err(zip_error(ZipError::UnsupportedCompression(method)))
+        }
+    }
+
+    fn extract(&self, file: &AssetPath) -> IoResult<Vec<u8>> {
+        let entry = try!(self.entry(file));
+        try!(self.limits.check(file.as_str(), entry.size));
+
+        let data = try!(self.limits.read_to_end(file.as_str(), try!(self.inflate(entry))));
+        if data.len() as u64 != entry.size {
+            return Err(zip_error(ZipError::InvalidArchive("member is shorter than recorded")));
+        }
+
+        let mut crc = Crc::new();
+        crc.update(&data);
+        if crc.sum() != entry.crc {
+            return Err(zip_error(ZipError::BadCrc {
+                path: file.to_string(),
+                expected: entry.crc,
+                actual: crc.sum(),
+            }));
+        }
+        Ok(data)
+    }
+}
+
+impl IoBackend for ZipBackend {
+    fn go_get(&self, file: &AssetPath, delivery: Delivery) {
+        let backend = self.clone();
+        let file = file.clone();
+        thread::spawn(move || {
+            delivery.deliver(backend.extract(&file));
+        });
+    }
+
+    fn metadata(&self, file: &AssetPath) -> IoResult<AssetMetadata> {
+        let entry = try!(self.entry(file));
+        let mut meta = AssetMetadata::new();
+        meta.size = Some(entry.size);
+        meta.modified = entry.modified;
+        meta.content_type = guess_content_type(file.as_str());
+        meta.etag = Some(format!("{:08x}", entry.crc));
+        Ok(meta)
+    }
+
+    /// Compressed members cannot be seeked, so the member is inflated
+    /// up front and the reader works on the inflated bytes.
+    fn open(&self, file: &AssetPath) -> IoResult<Box<ReadSeek>> {
+        Ok(Box::new(Cursor::new(try!(self.extract(file)))))
+    }
+
+    /// Stored members are read straight from the archive, deflated
+    /// ones are inflated only as far as the end of the range.  The crc
+    /// covers whole members, so it isn't checked here.
+    fn read_range(&self, file: &AssetPath, offset: u64, len: u64) -> IoResult<Vec<u8>> {
+        let entry = try!(self.entry(file));
+        let offset = cmp::min(offset, entry.size);
+        let len = cmp::min(len, entry.size - offset);
+
+        let mut data = vec![];
+        if entry.method == STORED {
+            let mut archive = try!(self.member(entry));
+            try!(archive.seek(SeekFrom::Current(offset as i64)));
+            try!(archive.take(len).read_to_end(&mut data));
+        } else {
+            let mut inflated = try!(self.inflate(entry));
+            try!(io::copy(&mut inflated.by_ref().take(offset), &mut io::sink()));
+            try!(inflated.take(len).read_to_end(&mut data));
+        }
+        Ok(data)
+    }
+}
+
+impl ListBackend for ZipBackend {
+    fn list(&self) -> IoResult<Vec<String>> {
+        let mut all: Vec<String> = self.entries.keys().cloned().collect();
+        all.sort();
+        Ok(all)
+    }
+}